@@ -0,0 +1,71 @@
+// Copyright (c) 2015 William Light <wrl@illest.net>
+// 
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// 
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// 
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::{error, fmt};
+use std::ffi::CStr;
+
+use libc::c_int;
+
+use alsa_sys::snd_strerror;
+
+#[derive(Debug)]
+pub enum Error {
+    // an alsa call failed. `code` is the (negative) errno it returned and `message` is what
+    // snd_strerror() had to say about it.
+    Alsa {
+        code: c_int,
+        message: String
+    },
+
+    // a client, port or queue name (or address string) with an interior nul byte.
+    InvalidName(String),
+
+    // a midi message with no sequencer event equivalent.
+    UnsupportedMessage,
+
+    // a queue tempo (in bpm) which isn't a positive, finite number.
+    InvalidTempo(f32)
+}
+
+impl Error {
+    pub fn from_code(code: c_int) -> Error {
+        let message = unsafe {
+            CStr::from_ptr(snd_strerror(code)).to_string_lossy().into_owned()
+        };
+
+        Error::Alsa {
+            code: code,
+            message: message
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Alsa { code, ref message } => write!(f, "{} (alsa error {})", message, code),
+            Error::InvalidName(ref name) => write!(f, "invalid name {:?}", name),
+            Error::UnsupportedMessage => write!(f, "message not supported by the sequencer"),
+            Error::InvalidTempo(bpm) => write!(f, "invalid tempo {} bpm", bpm)
+        }
+    }
+}
+
+impl error::Error for Error {}
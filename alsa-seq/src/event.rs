@@ -18,9 +18,13 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use std::{ptr, slice};
+
 use libc::{
+    c_int,
     c_uint,
-    c_uchar
+    c_uchar,
+    c_void
 };
 
 use midi::*;
@@ -37,15 +41,83 @@ const SND_SEQ_ADDRESS_UNKNOWN: c_uchar = 253;
 
 const SND_SEQ_EVENT_LENGTH_MASK: c_uint = 3 << 2;
 const SND_SEQ_EVENT_LENGTH_FIXED: c_uint = 0 << 2;
+const SND_SEQ_EVENT_LENGTH_VARIABLE: c_uint = 1 << 2;
+
+const SND_SEQ_TIME_STAMP_MASK: c_uint = 1 << 0;
+const SND_SEQ_TIME_STAMP_TICK: c_uint = 0 << 0;
+const SND_SEQ_TIME_STAMP_REAL: c_uint = 1 << 0;
+
+const SND_SEQ_TIME_MODE_MASK: c_uint = 1 << 1;
+const SND_SEQ_TIME_MODE_ABS: c_uint = 0 << 1;
+const SND_SEQ_TIME_MODE_REL: c_uint = 1 << 1;
+
+//
+// MIDI controller numbers for the channel mode messages, which ALSA sends as plain
+// controller events
+//
+
+const MIDI_CTL_ALL_SOUNDS_OFF: c_uint = 0x78;
+const MIDI_CTL_RESET_CONTROLLERS: c_uint = 0x79;
+const MIDI_CTL_LOCAL_CONTROL_SWITCH: c_uint = 0x7a;
+const MIDI_CTL_ALL_NOTES_OFF: c_uint = 0x7b;
+
+const PITCH_BEND_CENTER: c_int = 0x2000;
+
+const CHANNELS: [Channel; 16] = [
+    Ch1, Ch2, Ch3, Ch4, Ch5, Ch6, Ch7, Ch8,
+    Ch9, Ch10, Ch11, Ch12, Ch13, Ch14, Ch15, Ch16
+];
+
+// ALSA numbers channels 0-15.
+fn channel_to_alsa(channel: Channel) -> c_uchar {
+    CHANNELS.iter().position(|&ch| ch == channel).unwrap() as c_uchar
+}
+
+fn channel_from_alsa(channel: c_uchar) -> Option<Channel> {
+    CHANNELS.get(channel as usize).cloned()
+}
+
+pub fn empty_event() -> snd_seq_event_t {
+    snd_seq_event_t {
+        _type: 0,
+        flags: 0,
+        tag: 0,
+        queue: 0,
+
+        time: snd_seq_timestamp_t { 
+            data: [0; 2]
+        },
+
+        source: snd_seq_addr_t {
+            client: 0,
+            port: 0
+        },
+
+        dest: snd_seq_addr_t {
+            client: 0,
+            port: 0
+        },
+
+        data: Union_Unnamed10 {
+            data: [0; 3]
+        }
+    }
+}
 
 pub trait TransliteratedFromCMacros {
     fn set_fixed(&mut self);
+    fn set_variable(&mut self, len: c_uint, ptr: *mut c_void);
     fn set_direct(&mut self);
+    fn schedule_tick(&mut self, queue: c_uchar, relative: bool, tick: c_uint);
+    fn schedule_real(&mut self, queue: c_uchar, relative: bool, secs: c_uint, nsecs: c_uint);
     fn set_subs(&mut self);
     fn set_source(&mut self, port: c_uchar);
 
     fn set_note(&mut self, _type: c_uint, channel: Channel, note_number: u8, velocity: u8,
                 duration: c_uint);
+    fn set_controller(&mut self, _type: c_uint, channel: Channel, param: c_uint, value: c_int);
+    fn set_realtime(&mut self, _type: c_uint);
+    fn set_sysex(&mut self, len: c_uint, ptr: *mut c_void);
 }
 
 impl TransliteratedFromCMacros for snd_seq_event_t {
@@ -55,11 +127,45 @@ impl TransliteratedFromCMacros for snd_seq_event_t {
         self.flags |= SND_SEQ_EVENT_LENGTH_FIXED as u8;
     }
 
+    #[inline]
+    fn set_variable(&mut self, len: c_uint, ptr: *mut c_void) {
+        self.flags &= !(SND_SEQ_EVENT_LENGTH_MASK as u8);
+        self.flags |= SND_SEQ_EVENT_LENGTH_VARIABLE as u8;
+
+        // snd_seq_ev_ext_t is packed in seq_event.h, but alsa-sys's binding of it isn't, so
+        // data.ext() would put the pointer in the wrong place (and past the end of the union).
+        let ext = &mut self.data as *mut Union_Unnamed10 as *mut u8;
+        unsafe {
+            ptr::write_unaligned(ext as *mut c_uint, len);
+            ptr::write_unaligned(ext.offset(4) as *mut *mut c_void, ptr);
+        }
+    }
+
     #[inline]
     fn set_direct(&mut self) {
         self.queue = SND_SEQ_QUEUE_DIRECT;
     }
 
+    #[inline]
+    fn schedule_tick(&mut self, queue: c_uchar, relative: bool, tick: c_uint) {
+        self.flags &= !((SND_SEQ_TIME_STAMP_MASK | SND_SEQ_TIME_MODE_MASK) as u8);
+        self.flags |= SND_SEQ_TIME_STAMP_TICK as u8;
+        self.flags |= if relative { SND_SEQ_TIME_MODE_REL } else { SND_SEQ_TIME_MODE_ABS } as u8;
+
+        self.time.data = [tick, 0];
+        self.queue = queue;
+    }
+
+    #[inline]
+    fn schedule_real(&mut self, queue: c_uchar, relative: bool, secs: c_uint, nsecs: c_uint) {
+        self.flags &= !((SND_SEQ_TIME_STAMP_MASK | SND_SEQ_TIME_MODE_MASK) as u8);
+        self.flags |= SND_SEQ_TIME_STAMP_REAL as u8;
+        self.flags |= if relative { SND_SEQ_TIME_MODE_REL } else { SND_SEQ_TIME_MODE_ABS } as u8;
+
+        self.time.data = [secs, nsecs];
+        self.queue = queue;
+    }
+
     #[inline]
     fn set_subs(&mut self) {
         self.dest.client = SND_SEQ_ADDRESS_SUBSCRIBERS;
@@ -79,45 +185,52 @@ impl TransliteratedFromCMacros for snd_seq_event_t {
 
         let note = self.data.note();
         unsafe {
-            (*note).channel = (channel as c_uchar) + 1;
+            (*note).channel = channel_to_alsa(channel);
             (*note).note = note_number;
             (*note).velocity = velocity;
             (*note).duration = duration;
         }
     }
 
+    // covers snd_seq_ev_set_controller(), _pgmchange(), _chanpress() and _pitchbend(), which
+    // only differ in the type and in which fields they leave at zero.
+    #[inline]
+    fn set_controller(&mut self, _type: c_uint, channel: Channel, param: c_uint, value: c_int) {
+        self._type = _type as snd_seq_event_type_t;
+        self.set_fixed();
+
+        let control = self.data.control();
+        unsafe {
+            (*control).channel = channel_to_alsa(channel);
+            (*control).param = param;
+            (*control).value = value;
+        }
+    }
+
+    // system realtime messages carry no data, this is what snd_midi_event_encode() produces
+    // for them.
+    #[inline]
+    fn set_realtime(&mut self, _type: c_uint) {
+        self._type = _type as snd_seq_event_type_t;
+        self.set_fixed();
+    }
+
+    #[inline]
+    fn set_sysex(&mut self, len: c_uint, ptr: *mut c_void) {
+        self._type = SND_SEQ_EVENT_SYSEX as snd_seq_event_type_t;
+        self.set_variable(len, ptr);
+    }
 }
 
+// `data` is where the bytes of variable-length events (sysex) end up. the event points into
+// it rather than holding on to them, so it has to be left alone until the event is output.
 pub trait ToSndSeqEvent {
-    fn to_snd_seq_event(&self) -> Option<snd_seq_event_t>;
+    fn to_snd_seq_event(&self, data: &mut Vec<u8>) -> Option<snd_seq_event_t>;
 }
 
 impl ToSndSeqEvent for Message {
-    fn to_snd_seq_event(&self) -> Option<snd_seq_event_t> {
-        let mut ev = snd_seq_event_t {
-            _type: 0,
-            flags: 0,
-            tag: 0,
-            queue: 0,
-
-            time: snd_seq_timestamp_t { 
-                data: [0; 2]
-            },
-
-            source: snd_seq_addr_t {
-                client: 0,
-                port: 0
-            },
-
-            dest: snd_seq_addr_t {
-                client: 0,
-                port: 0
-            },
-
-            data: Union_Unnamed10 {
-                data: [0; 3]
-            }
-        };
+    fn to_snd_seq_event(&self, data: &mut Vec<u8>) -> Option<snd_seq_event_t> {
+        let mut ev = empty_event();
 
         match *self {
             Message::NoteOn(channel, note_number, velocity) =>
@@ -129,9 +242,278 @@ impl ToSndSeqEvent for Message {
             Message::PolyphonicPressure(channel, note_number, velocity) =>
                 ev.set_note(SND_SEQ_EVENT_KEYPRESS, channel, note_number, velocity, 0),
 
-            _ => return None
+            Message::ControlChange(channel, controller, value) =>
+                ev.set_controller(SND_SEQ_EVENT_CONTROLLER, channel, controller as c_uint,
+                                  value as c_int),
+
+            Message::AllSoundOff(channel) =>
+                ev.set_controller(SND_SEQ_EVENT_CONTROLLER, channel, MIDI_CTL_ALL_SOUNDS_OFF, 0),
+
+            Message::ResetAllControllers(channel) =>
+                ev.set_controller(SND_SEQ_EVENT_CONTROLLER, channel, MIDI_CTL_RESET_CONTROLLERS,
+                                  0),
+
+            Message::LocalControlOff(channel) =>
+                ev.set_controller(SND_SEQ_EVENT_CONTROLLER, channel,
+                                  MIDI_CTL_LOCAL_CONTROL_SWITCH, 0),
+
+            Message::LocalControlOn(channel) =>
+                ev.set_controller(SND_SEQ_EVENT_CONTROLLER, channel,
+                                  MIDI_CTL_LOCAL_CONTROL_SWITCH, 127),
+
+            Message::AllNotesOff(channel) =>
+                ev.set_controller(SND_SEQ_EVENT_CONTROLLER, channel, MIDI_CTL_ALL_NOTES_OFF, 0),
+
+            Message::ProgramChange(channel, program) =>
+                ev.set_controller(SND_SEQ_EVENT_PGMCHANGE, channel, 0, program as c_int),
+
+            Message::ChannelPressure(channel, pressure) =>
+                ev.set_controller(SND_SEQ_EVENT_CHANPRESS, channel, 0, pressure as c_int),
+
+            // ALSA wants the bend as a signed value centred on zero
+            Message::PitchBend(channel, bend) =>
+                ev.set_controller(SND_SEQ_EVENT_PITCHBEND, channel, 0,
+                                  (bend & 0x3FFF) as c_int - PITCH_BEND_CENTER),
+
+            // the complete message, from the 0xF0 to the 0xF7, the way the MIDI 1.0 wire
+            // format has it.
+            Message::SysEx(manufacturer, ref bytes) => {
+                data.clear();
+                data.push(0xF0);
+                data.extend(manufacturer.to_u7s());
+                data.extend(bytes.iter().map(|&byte| byte & 0x7F));
+                data.push(0xF7);
+
+                ev.set_sysex(data.len() as c_uint, data.as_mut_ptr() as *mut c_void);
+            },
+
+            Message::TimingClock => ev.set_realtime(SND_SEQ_EVENT_CLOCK),
+            Message::Start => ev.set_realtime(SND_SEQ_EVENT_START),
+            Message::Continue => ev.set_realtime(SND_SEQ_EVENT_CONTINUE),
+            Message::Stop => ev.set_realtime(SND_SEQ_EVENT_STOP),
+            Message::ActiveSensing => ev.set_realtime(SND_SEQ_EVENT_SENSING),
+            Message::SystemReset => ev.set_realtime(SND_SEQ_EVENT_RESET),
+
+            // these go out as a run of controller changes, which doesn't fit in one event.
+            Message::RPN7(..) | Message::RPN14(..) | Message::NRPN7(..) | Message::NRPN14(..) =>
+                return None
         }
 
         Some(ev)
     }
 }
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Address {
+    pub client: u8,
+    pub port: u8
+}
+
+// the system client's announce port, which broadcasts the client and port start, exit and
+// change events along with (un)subscriptions.
+pub const SYSTEM_ANNOUNCE: Address = Address {
+    client: 0,
+    port: 1
+};
+
+impl Address {
+    fn from_alsa(addr: &snd_seq_addr_t) -> Self {
+        Address {
+            client: addr.client,
+            port: addr.port
+        }
+    }
+}
+
+// everything which can arrive from the sequencer. the MIDI events come back as a midi::Message
+// where there's a variant for them, the rest of the types we know about are the sequencer's
+// own announcements.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Midi(Message),
+
+    // the complete message, including the leading 0xF0 and trailing 0xF7
+    SysEx(Vec<u8>),
+
+    // a 14-bit controller (0-31) with its 14-bit value, which goes out over MIDI as a
+    // change of the controller followed by one of its LSB controller (32-63). ALSA sends
+    // 14-bit changes of the other controllers as plain 7-bit ones, see `Event::Midi`.
+    ControlChange14(Channel, u8, u16),
+
+    ClientStart(u8),
+    ClientExit(u8),
+    ClientChange(u8),
+
+    PortStart(Address),
+    PortExit(Address),
+    PortChange(Address),
+
+    PortSubscribed {
+        sender: Address,
+        dest: Address
+    },
+
+    PortUnsubscribed {
+        sender: Address,
+        dest: Address
+    },
+
+    // any other event type, by its SND_SEQ_EVENT_* number
+    Other(u8)
+}
+
+// gives None for events which don't map to a value of the type.
+pub trait FromSndSeqEvent: Sized {
+    fn from_snd_seq_event(ev: &snd_seq_event_t) -> Option<Self>;
+}
+
+// the union accessors in alsa-sys want a mutable event, which we don't have (or need).
+unsafe fn event_data<T>(ev: &snd_seq_event_t) -> &T {
+    &*(&ev.data as *const Union_Unnamed10 as *const T)
+}
+
+fn controller_to_message(channel: Channel, param: c_uint, value: c_int) -> Message {
+    match param {
+        MIDI_CTL_ALL_SOUNDS_OFF => Message::AllSoundOff(channel),
+        MIDI_CTL_RESET_CONTROLLERS => Message::ResetAllControllers(channel),
+
+        MIDI_CTL_LOCAL_CONTROL_SWITCH if value == 0 => Message::LocalControlOff(channel),
+        MIDI_CTL_LOCAL_CONTROL_SWITCH => Message::LocalControlOn(channel),
+
+        MIDI_CTL_ALL_NOTES_OFF => Message::AllNotesOff(channel),
+
+        _ => Message::ControlChange(channel, (param & 0x7F) as u8, (value & 0x7F) as u8)
+    }
+}
+
+impl FromSndSeqEvent for Message {
+    fn from_snd_seq_event(ev: &snd_seq_event_t) -> Option<Self> {
+        let _type = ev._type as c_uint;
+
+        match _type {
+            SND_SEQ_EVENT_NOTE | SND_SEQ_EVENT_NOTEON | SND_SEQ_EVENT_NOTEOFF
+                | SND_SEQ_EVENT_KEYPRESS => {
+                let note: &snd_seq_ev_note_t = unsafe { event_data(ev) };
+
+                let channel = match channel_from_alsa(note.channel) {
+                    Some(channel) => channel,
+                    None => return None
+                };
+
+                Some(match _type {
+                    // a note with a duration is only turned into a note on and a note off
+                    // when it goes through a queue, so a direct one reaches us as is. the
+                    // duration is in the queue's time, which is of no use here.
+                    SND_SEQ_EVENT_NOTE | SND_SEQ_EVENT_NOTEON =>
+                        Message::NoteOn(channel, note.note, note.velocity),
+
+                    SND_SEQ_EVENT_NOTEOFF =>
+                        Message::NoteOff(channel, note.note, note.velocity),
+
+                    _ =>
+                        Message::PolyphonicPressure(channel, note.note, note.velocity)
+                })
+            },
+
+            SND_SEQ_EVENT_CONTROLLER | SND_SEQ_EVENT_PGMCHANGE | SND_SEQ_EVENT_CHANPRESS
+                | SND_SEQ_EVENT_PITCHBEND => {
+                let control: &snd_seq_ev_ctrl_t = unsafe { event_data(ev) };
+
+                let channel = match channel_from_alsa(control.channel) {
+                    Some(channel) => channel,
+                    None => return None
+                };
+
+                Some(match _type {
+                    SND_SEQ_EVENT_CONTROLLER =>
+                        controller_to_message(channel, control.param, control.value),
+
+                    SND_SEQ_EVENT_PGMCHANGE =>
+                        Message::ProgramChange(channel, (control.value & 0x7F) as u8),
+
+                    SND_SEQ_EVENT_CHANPRESS =>
+                        Message::ChannelPressure(channel, (control.value & 0x7F) as u8),
+
+                    _ => {
+                        let bend = (control.value + PITCH_BEND_CENTER).max(0).min(0x3FFF);
+                        Message::PitchBend(channel, bend as U14)
+                    }
+                })
+            },
+
+            SND_SEQ_EVENT_CLOCK => Some(Message::TimingClock),
+            SND_SEQ_EVENT_START => Some(Message::Start),
+            SND_SEQ_EVENT_CONTINUE => Some(Message::Continue),
+            SND_SEQ_EVENT_STOP => Some(Message::Stop),
+            SND_SEQ_EVENT_SENSING => Some(Message::ActiveSensing),
+            SND_SEQ_EVENT_RESET => Some(Message::SystemReset),
+
+            _ => None
+        }
+    }
+}
+
+// every event decodes to something, the types we don't know about end up as Event::Other.
+impl Event {
+    pub fn from_snd_seq_event(ev: &snd_seq_event_t) -> Event {
+        if let Some(msg) = Message::from_snd_seq_event(ev) {
+            return Event::Midi(msg);
+        }
+
+        let addr: &snd_seq_addr_t = unsafe { event_data(ev) };
+        let connect: &snd_seq_connect_t = unsafe { event_data(ev) };
+        let control: &snd_seq_ev_ctrl_t = unsafe { event_data(ev) };
+
+        match ev._type as c_uint {
+            SND_SEQ_EVENT_CONTROL14 => {
+                let channel = match channel_from_alsa(control.channel) {
+                    Some(channel) => channel,
+                    None => return Event::Other(ev._type)
+                };
+
+                if control.param < 0x20 {
+                    Event::ControlChange14(channel, control.param as u8,
+                                           (control.value & 0x3FFF) as u16)
+                } else {
+                    Event::Midi(controller_to_message(channel, control.param, control.value))
+                }
+            },
+
+            SND_SEQ_EVENT_SYSEX => {
+                // see set_variable() for why this doesn't go through data.ext()
+                let ext = &ev.data as *const Union_Unnamed10 as *const u8;
+
+                unsafe {
+                    let len = ptr::read_unaligned(ext as *const c_uint);
+                    let data = ptr::read_unaligned(ext.offset(4) as *const *const u8);
+
+                    if data.is_null() {
+                        Event::SysEx(Vec::new())
+                    } else {
+                        Event::SysEx(slice::from_raw_parts(data, len as usize).to_vec())
+                    }
+                }
+            },
+
+            SND_SEQ_EVENT_CLIENT_START => Event::ClientStart(addr.client),
+            SND_SEQ_EVENT_CLIENT_EXIT => Event::ClientExit(addr.client),
+            SND_SEQ_EVENT_CLIENT_CHANGE => Event::ClientChange(addr.client),
+
+            SND_SEQ_EVENT_PORT_START => Event::PortStart(Address::from_alsa(addr)),
+            SND_SEQ_EVENT_PORT_EXIT => Event::PortExit(Address::from_alsa(addr)),
+            SND_SEQ_EVENT_PORT_CHANGE => Event::PortChange(Address::from_alsa(addr)),
+
+            SND_SEQ_EVENT_PORT_SUBSCRIBED => Event::PortSubscribed {
+                sender: Address::from_alsa(&connect.sender),
+                dest: Address::from_alsa(&connect.dest)
+            },
+
+            SND_SEQ_EVENT_PORT_UNSUBSCRIBED => Event::PortUnsubscribed {
+                sender: Address::from_alsa(&connect.sender),
+                dest: Address::from_alsa(&connect.dest)
+            },
+
+            _ => Event::Other(ev._type)
+        }
+    }
+}
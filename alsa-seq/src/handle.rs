@@ -19,13 +19,27 @@
 // SOFTWARE.
 
 use std::ptr::null_mut;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::ffi;
 
+use libc::{
+    c_int,
+    c_uint,
+    size_t,
+    pollfd,
+    POLLIN,
+    EAGAIN
+};
+
 use alsa_sys::*;
 
 use {
+    RawHandle,
     SequencerHandle,
     SequencerPort,
+    SYSTEM_ANNOUNCE,
+    Address,
+    Event,
     Error,
     PortType,
     PortCapabilities
@@ -42,47 +56,55 @@ impl SequencerHandle {
     pub fn open(name: &str, streams: HandleOpenStreams) -> Result<Self, Error> {
         let cstr = match ffi::CString::new(name) {
             Ok(cstr) => cstr,
-            Err(_) => return Err(Error::Unknown)
+            Err(_) => return Err(Error::InvalidName(name.to_string()))
         };
 
-        let mut inst = SequencerHandle {
-            raw_handle: null_mut()
-        };
+        let mut raw = RawHandle(null_mut());
 
         unsafe {
-            let err = snd_seq_open(&mut inst.raw_handle, b"default\0".as_ptr() as *const i8,
+            let err = snd_seq_open(&mut raw.0, b"default\0".as_ptr() as *const i8,
                                    streams as i32, 0);
 
             if err != 0 {
-                return Err(Error::Unknown);
+                return Err(Error::from_code(err));
             }
 
-            if snd_seq_set_client_name(inst.raw_handle, cstr.as_ptr()) != 0 {
-                return Err(Error::Unknown);
-            } else {
-                return Ok(inst);
+            match snd_seq_set_client_name(raw.0, cstr.as_ptr()) {
+                0 => {},
+                err_code @ _ => return Err(Error::from_code(err_code))
             }
         }
+
+        Ok(SequencerHandle {
+            raw_handle: Arc::new(Mutex::new(raw))
+        })
+    }
+
+    // locks the handle for the duration of an alsa call (or a few of them). a panic while
+    // holding the lock can't leave the snd_seq_t itself in a bad state, so poisoning is
+    // ignored.
+    pub(crate) fn raw(&self) -> MutexGuard<RawHandle> {
+        self.raw_handle.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
     }
 
     pub fn create_port(&self, name: &str, capabilities: PortCapabilities, port_type: PortType) 
         -> Result<SequencerPort, Error> {
         let cstr = match ffi::CString::new(name) {
             Ok(cstr) => cstr,
-            Err(_) => return Err(Error::Unknown)
+            Err(_) => return Err(Error::InvalidName(name.to_string()))
         };
 
         let mut port = SequencerPort {
             raw_handle: -1,
-            handle: self
+            handle: self.clone()
         };
 
         unsafe {
-            let port_nr = snd_seq_create_simple_port(self.raw_handle, cstr.as_ptr(),
+            let port_nr = snd_seq_create_simple_port(self.raw().0, cstr.as_ptr(),
                 capabilities.bits(), port_type as u32);
 
             if port_nr < 0 {
-                return Err(Error::Unknown);
+                return Err(Error::from_code(port_nr));
             } else {
                 port.raw_handle = port_nr;
                 return Ok(port);
@@ -90,21 +112,164 @@ impl SequencerHandle {
         }
     }
 
-    pub fn drain_output(&self) {
+    // creates a (hidden) port subscribed to the system announce port. the announcements then
+    // come in through `recv_event()` as `Event::ClientStart`, `Event::PortExit` and so on,
+    // for as long as the returned port is kept around.
+    pub fn subscribe_announcements(&self) -> Result<SequencerPort, Error> {
+        let port = self.create_port("announcements",
+                                    PortCapabilities::PORT_CAPABILITY_WRITE
+                                    | PortCapabilities::PORT_CAPABILITY_NO_EXPORT,
+                                    PortType::Application)?;
+
+        port.connect_from(SYSTEM_ANNOUNCE)?;
+        Ok(port)
+    }
+
+    // resolves a "client:port" string into an address. the client can be given by number or
+    // by name (or a prefix of it), and the port defaults to 0 if it's left out.
+    pub fn parse_address(&self, address: &str) -> Result<Address, Error> {
+        let cstr = match ffi::CString::new(address) {
+            Ok(cstr) => cstr,
+            Err(_) => return Err(Error::InvalidName(address.to_string()))
+        };
+
+        let mut addr = snd_seq_addr_t {
+            client: 0,
+            port: 0
+        };
+
         unsafe {
-            snd_seq_drain_output(self.raw_handle);
+            match snd_seq_parse_address(self.raw().0, &mut addr, cstr.as_ptr()) {
+                err_code @ _ if err_code < 0 => return Err(Error::from_code(err_code)),
+                _ => {}
+            }
+        }
+
+        Ok(Address {
+            client: addr.client,
+            port: addr.port
+        })
+    }
+
+    // with non-blocking mode on, `recv_event()` returns straight away when there's nothing
+    // to read. alsa only has the one mode for the whole handle though, so sending and
+    // draining then fail with EAGAIN instead of waiting when the output is full. to keep
+    // output blocking, leave this off and use `poll_descriptors()` and `input_pending()`.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
+        unsafe {
+            match snd_seq_nonblock(self.raw().0, nonblocking as c_int) {
+                err_code @ _ if err_code < 0 => return Err(Error::from_code(err_code)),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    // our client number, which together with a port's number makes up its address.
+    pub fn client_id(&self) -> u8 {
+        unsafe {
+            snd_seq_client_id(self.raw().0) as u8
+        }
+    }
+
+    // the fds to poll() on for incoming events. only meaningful if the handle was opened
+    // with input.
+    pub fn poll_descriptors(&self) -> Vec<pollfd> {
+        unsafe {
+            let count = snd_seq_poll_descriptors_count(self.raw().0, POLLIN);
+
+            if count <= 0 {
+                return Vec::new();
+            }
+
+            let mut fds = vec![pollfd { fd: -1, events: 0, revents: 0 }; count as usize];
+            let filled = snd_seq_poll_descriptors(self.raw().0, fds.as_mut_ptr(),
+                                                  count as c_uint, POLLIN);
+
+            fds.truncate(filled.max(0) as usize);
+            fds
+        }
+    }
+
+    // the number of events which have already been read from the sequencer and are waiting
+    // in the input buffer. it doesn't read any more itself, so it never blocks, and as long
+    // as it's non-zero neither does `recv_event()`.
+    pub fn input_pending(&self) -> usize {
+        unsafe {
+            snd_seq_event_input_pending(self.raw().0, 0).max(0) as usize
+        }
+    }
+
+    // reads the next incoming event along with the number of the port it was sent to. in
+    // blocking mode this waits for one, in non-blocking mode it returns Ok(None) once there
+    // are no more events waiting.
+    pub fn recv_event(&self) -> Result<Option<(c_int, Event)>, Error> {
+        let mut ev: *mut snd_seq_event_t = null_mut();
+
+        // `ev` points into alsa's input buffer, so the lock has to be held until we're done
+        // decoding it.
+        let raw = self.raw();
+
+        unsafe {
+            match snd_seq_event_input(raw.0, &mut ev) {
+                err_code @ _ if err_code == -EAGAIN => return Ok(None),
+                err_code @ _ if err_code < 0 => return Err(Error::from_code(err_code)),
+                _ => {}
+            }
+
+            let event = Event::from_snd_seq_event(&*ev);
+            Ok(Some(((*ev).dest.port as c_int, event)))
+        }
+    }
+
+    // events sent on this handle's ports pile up in its output buffer until they're drained,
+    // or until the buffer fills up, at which point alsa drains it by itself. returns the
+    // number of bytes still waiting to go out, which can only be non-zero in non-blocking
+    // mode.
+    pub fn drain_output(&self) -> Result<usize, Error> {
+        unsafe {
+            match snd_seq_drain_output(self.raw().0) {
+                err_code @ _ if err_code == -EAGAIN => Ok(self.output_pending()),
+                err_code @ _ if err_code < 0 => Err(Error::from_code(err_code)),
+                remaining => Ok(remaining as usize)
+            }
+        }
+    }
+
+    // the number of bytes of events in the output buffer that haven't been drained yet.
+    pub fn output_pending(&self) -> usize {
+        unsafe {
+            snd_seq_event_output_pending(self.raw().0).max(0) as usize
+        }
+    }
+
+    pub fn output_buffer_size(&self) -> usize {
+        unsafe {
+            snd_seq_get_output_buffer_size(self.raw().0) as usize
+        }
+    }
+
+    // each event takes up 28 bytes of the buffer, plus the length of its data for sysex.
+    // resizing drops anything that's in the buffer at the time.
+    pub fn set_output_buffer_size(&self, size: usize) -> Result<(), Error> {
+        unsafe {
+            match snd_seq_set_output_buffer_size(self.raw().0, size as size_t) {
+                err_code @ _ if err_code < 0 => Err(Error::from_code(err_code)),
+                _ => Ok(())
+            }
         }
     }
 }
 
-impl Drop for SequencerHandle {
+impl Drop for RawHandle {
     fn drop(&mut self) {
-        if self.raw_handle.is_null() {
+        if self.0.is_null() {
             return;
         }
 
         unsafe {
-            snd_seq_close(self.raw_handle);
+            snd_seq_close(self.0);
         }
     }
 }
@@ -26,26 +26,43 @@ extern crate bitflags;
 extern crate midi;
 extern crate alsa_sys;
 
+use std::sync::{Arc, Mutex};
+
 use libc::*;
 use alsa_sys::*;
 
+// alsa makes no promises about using one snd_seq_t from several threads at the same time, so
+// it's only ever touched with the lock held (see `SequencerHandle::raw()`). moving it from one
+// thread to another is fine, though.
+struct RawHandle(*mut snd_seq_t);
+
+unsafe impl Send for RawHandle {}
+
+// cloning a handle is cheap and gives another reference to the same sequencer client, which
+// is closed once the last handle, port and queue referring to it is gone.
+#[derive(Clone)]
 pub struct SequencerHandle {
-    raw_handle: *mut snd_seq_t
+    raw_handle: Arc<Mutex<RawHandle>>
 }
 
-pub struct SequencerPort<'handle> {
+// ports and queues hold on to a handle of their own, so they can be stored and sent to other
+// threads independently of the handle they were created with.
+pub struct SequencerPort {
     raw_handle: c_int,
-    handle: &'handle SequencerHandle
+    handle: SequencerHandle
 }
 
-#[derive(Debug)]
-pub enum Error {
-    Unknown
+pub struct SequencerQueue {
+    raw_handle: c_int,
+    handle: SequencerHandle
 }
 
+mod error;
 mod handle;
 mod port;
 
+pub use error::Error;
+
 pub use handle::{
     HandleOpenStreams,
 };
@@ -57,4 +74,30 @@ pub use port::{
 
 mod event;
 
+pub use event::{
+    Address,
+    Event,
+    SYSTEM_ANNOUNCE
+};
+
+mod query;
+mod queue;
+
+pub use queue::{
+    QueueStatus,
+    Timestamp
+};
+
+pub use query::{
+    ClientInfo,
+    PortInfo,
+    Clients,
+    Ports
+};
+
+mod subscription;
+
+pub use subscription::PortSubscription;
+
+#[cfg(test)]
 mod test;
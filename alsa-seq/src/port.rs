@@ -24,11 +24,17 @@ use midi::*;
 use alsa_sys::*;
 
 use {
+    SequencerHandle,
     SequencerPort,
+    SequencerQueue,
+    Address,
     Error,
 };
 
+use queue::Timestamp;
+
 use event::{
+    empty_event,
     ToSndSeqEvent,
     TransliteratedFromCMacros
 };
@@ -38,6 +44,7 @@ bitflags! {
         const PORT_CAPABILITY_DUPLEX = (1 << 4);
         const PORT_CAPABILITY_NO_EXPORT = (1 << 7);
         const PORT_CAPABILITY_READ = (1 << 0);
+        const PORT_CAPABILITY_WRITE = (1 << 1);
         const PORT_CAPABILITY_SUBS_READ = (1 << 5);
         const PORT_CAPABILITY_SUBS_WRITE = (1 << 6);
         const PORT_CAPABILITY_SYNC_READ = (1 << 2);
@@ -46,6 +53,7 @@ bitflags! {
 }
 
 #[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum PortType {
     Application = 1 << 20,
     DirectSample = 1 << 11,
@@ -64,21 +72,124 @@ pub enum PortType {
     Synthesizer = 1 << 18
 }
 
-impl<'handle> SequencerPort<'handle> {
+// every port type, for picking apart the type bitmask of a port found by enumeration.
+pub const ALL_PORT_TYPES: [PortType; 15] = [
+    PortType::Application,
+    PortType::DirectSample,
+    PortType::Hardware,
+    PortType::MidiGeneric,
+    PortType::MidiGM,
+    PortType::MidiGM2,
+    PortType::MidiGS,
+    PortType::MidiMT32,
+    PortType::MidiXG,
+    PortType::Port,
+    PortType::Sample,
+    PortType::Software,
+    PortType::Specific,
+    PortType::Synth,
+    PortType::Synthesizer
+];
+
+impl SequencerPort {
+    // the port's number within our client, which is where incoming events addressed to this
+    // port end up (see `SequencerHandle::recv_event()`).
+    pub fn number(&self) -> c_int {
+        self.raw_handle
+    }
+
+    // the handle of the client the port belongs to.
+    pub fn handle(&self) -> &SequencerHandle {
+        &self.handle
+    }
+
+    // subscribes `dest` to everything sent from this port.
+    pub fn connect_to(&self, dest: Address) -> Result<(), Error> {
+        unsafe {
+            match snd_seq_connect_to(self.handle.raw().0, self.raw_handle,
+                                     dest.client as c_int, dest.port as c_int) {
+                err_code @ _ if err_code < 0 => Err(Error::from_code(err_code)),
+                _ => Ok(())
+            }
+        }
+    }
+
+    // subscribes this port to everything sent from `src`.
+    pub fn connect_from(&self, src: Address) -> Result<(), Error> {
+        unsafe {
+            match snd_seq_connect_from(self.handle.raw().0, self.raw_handle,
+                                       src.client as c_int, src.port as c_int) {
+                err_code @ _ if err_code < 0 => Err(Error::from_code(err_code)),
+                _ => Ok(())
+            }
+        }
+    }
+
+    pub fn disconnect_to(&self, dest: Address) -> Result<(), Error> {
+        unsafe {
+            match snd_seq_disconnect_to(self.handle.raw().0, self.raw_handle,
+                                        dest.client as c_int, dest.port as c_int) {
+                err_code @ _ if err_code < 0 => Err(Error::from_code(err_code)),
+                _ => Ok(())
+            }
+        }
+    }
+
+    pub fn disconnect_from(&self, src: Address) -> Result<(), Error> {
+        unsafe {
+            match snd_seq_disconnect_from(self.handle.raw().0, self.raw_handle,
+                                          src.client as c_int, src.port as c_int) {
+                err_code @ _ if err_code < 0 => Err(Error::from_code(err_code)),
+                _ => Ok(())
+            }
+        }
+    }
+
+    // the event only goes out once the handle's output buffer is drained, see
+    // `SequencerHandle::drain_output()`.
     pub fn send_message(&self, msg: &Message) -> Result<(), Error> {
-        let mut ev = match msg.to_snd_seq_event() {
+        let mut data = Vec::new();
+        let mut ev = match msg.to_snd_seq_event(&mut data) {
             Some(ev) => ev,
-            None => return Err(Error::Unknown)
+            None => return Err(Error::UnsupportedMessage)
         };
 
         ev.set_direct();
+        self.output_event(&mut ev)
+    }
+
+    // like `send_message()`, but the event sits in `queue` until `time` comes around.
+    pub fn schedule_message(&self, msg: &Message, queue: &SequencerQueue, time: Timestamp)
+        -> Result<(), Error> {
+        let mut data = Vec::new();
+        let mut ev = match msg.to_snd_seq_event(&mut data) {
+            Some(ev) => ev,
+            None => return Err(Error::UnsupportedMessage)
+        };
+
+        time.schedule(&mut ev, queue.number() as c_uchar);
+        self.output_event(&mut ev)
+    }
+
+    // `data` is the complete message, including the leading 0xF0 and trailing 0xF7.
+    pub fn send_sysex(&self, data: &[u8]) -> Result<(), Error> {
+        let mut ev = empty_event();
+
+        // alsa copies the data into its output buffer, it doesn't write through the pointer.
+        ev.set_sysex(data.len() as c_uint, data.as_ptr() as *mut c_void);
+
+        ev.set_direct();
+        self.output_event(&mut ev)
+    }
+
+    fn output_event(&self, ev: &mut snd_seq_event_t) -> Result<(), Error> {
         ev.set_subs();
 
         ev.set_source(self.raw_handle as c_uchar);
 
         unsafe {
-            match snd_seq_event_output(self.handle.raw_handle, &mut ev) {
-                err_code @ _ if err_code < 0 => return Err(Error::Unknown),
+            match snd_seq_event_output(self.handle.raw().0, ev) {
+                err_code @ _ if err_code < 0 => return Err(Error::from_code(err_code)),
                 _ => {}
             }
         }
@@ -87,14 +198,14 @@ impl<'handle> SequencerPort<'handle> {
     }
 }
 
-impl<'handle> Drop for SequencerPort<'handle> {
+impl Drop for SequencerPort {
     fn drop(&mut self) {
         if self.raw_handle < 0 {
             return;
         }
 
         unsafe {
-            snd_seq_delete_simple_port(self.handle.raw_handle, self.raw_handle);
+            snd_seq_delete_simple_port(self.handle.raw().0, self.raw_handle);
         }
     }
 }
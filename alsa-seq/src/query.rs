@@ -0,0 +1,200 @@
+// Copyright (c) 2015 William Light <wrl@illest.net>
+// 
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// 
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// 
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::ffi::CStr;
+use std::ptr::null_mut;
+
+use libc::{
+    c_char,
+    c_int
+};
+
+use alsa_sys::*;
+
+use {
+    SequencerHandle,
+    Address,
+    Error
+};
+
+use port::{
+    PortType,
+    PortCapabilities,
+    ALL_PORT_TYPES
+};
+
+#[derive(Clone, Debug)]
+pub struct ClientInfo {
+    pub number: u8,
+    pub name: String,
+    pub num_ports: usize
+}
+
+#[derive(Clone, Debug)]
+pub struct PortInfo {
+    pub address: Address,
+    pub name: String,
+    pub capabilities: PortCapabilities,
+    type_bits: u32
+}
+
+impl PortInfo {
+    // the types the port was created with, in the order of `PortType`'s declaration.
+    pub fn types(&self) -> Vec<PortType> {
+        ALL_PORT_TYPES.iter()
+            .cloned()
+            .filter(|&port_type| self.type_bits & (port_type as u32) != 0)
+            .collect()
+    }
+
+    pub fn has_type(&self, port_type: PortType) -> bool {
+        self.type_bits & (port_type as u32) != 0
+    }
+
+    // whether we could subscribe to this port to receive its events.
+    pub fn is_readable(&self) -> bool {
+        self.capabilities.contains(PortCapabilities::PORT_CAPABILITY_READ
+                                   | PortCapabilities::PORT_CAPABILITY_SUBS_READ)
+    }
+
+    // whether we could subscribe this port to our events.
+    pub fn is_writable(&self) -> bool {
+        self.capabilities.contains(PortCapabilities::PORT_CAPABILITY_WRITE
+                                   | PortCapabilities::PORT_CAPABILITY_SUBS_WRITE)
+    }
+}
+
+unsafe fn string_from_c(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+
+    CStr::from_ptr(ptr).to_string_lossy().into_owned()
+}
+
+// walks every client currently connected to the sequencer, ourselves included.
+pub struct Clients<'handle> {
+    handle: &'handle SequencerHandle,
+    info: *mut snd_seq_client_info_t
+}
+
+impl<'handle> Iterator for Clients<'handle> {
+    type Item = ClientInfo;
+
+    fn next(&mut self) -> Option<ClientInfo> {
+        unsafe {
+            if snd_seq_query_next_client(self.handle.raw().0, self.info) < 0 {
+                return None;
+            }
+
+            Some(ClientInfo {
+                number: snd_seq_client_info_get_client(self.info) as u8,
+                name: string_from_c(snd_seq_client_info_get_name(self.info)),
+                num_ports: snd_seq_client_info_get_num_ports(self.info).max(0) as usize
+            })
+        }
+    }
+}
+
+impl<'handle> Drop for Clients<'handle> {
+    fn drop(&mut self) {
+        unsafe {
+            snd_seq_client_info_free(self.info);
+        }
+    }
+}
+
+// walks the ports of a single client.
+pub struct Ports<'handle> {
+    handle: &'handle SequencerHandle,
+    info: *mut snd_seq_port_info_t
+}
+
+impl<'handle> Iterator for Ports<'handle> {
+    type Item = PortInfo;
+
+    fn next(&mut self) -> Option<PortInfo> {
+        unsafe {
+            if snd_seq_query_next_port(self.handle.raw().0, self.info) < 0 {
+                return None;
+            }
+
+            Some(PortInfo {
+                address: Address {
+                    client: snd_seq_port_info_get_client(self.info) as u8,
+                    port: snd_seq_port_info_get_port(self.info) as u8
+                },
+
+                name: string_from_c(snd_seq_port_info_get_name(self.info)),
+                capabilities: PortCapabilities::from_bits_truncate(
+                    snd_seq_port_info_get_capability(self.info)),
+                type_bits: snd_seq_port_info_get_type(self.info)
+            })
+        }
+    }
+}
+
+impl<'handle> Drop for Ports<'handle> {
+    fn drop(&mut self) {
+        unsafe {
+            snd_seq_port_info_free(self.info);
+        }
+    }
+}
+
+impl SequencerHandle {
+    pub fn clients(&self) -> Result<Clients, Error> {
+        let mut info = null_mut();
+
+        unsafe {
+            match snd_seq_client_info_malloc(&mut info) {
+                err_code @ _ if err_code < 0 => return Err(Error::from_code(err_code)),
+                _ => {}
+            }
+
+            // the query starts from the client after this one.
+            snd_seq_client_info_set_client(info, -1);
+        }
+
+        Ok(Clients {
+            handle: self,
+            info: info
+        })
+    }
+
+    pub fn ports(&self, client: u8) -> Result<Ports, Error> {
+        let mut info = null_mut();
+
+        unsafe {
+            match snd_seq_port_info_malloc(&mut info) {
+                err_code @ _ if err_code < 0 => return Err(Error::from_code(err_code)),
+                _ => {}
+            }
+
+            snd_seq_port_info_set_client(info, client as c_int);
+            snd_seq_port_info_set_port(info, -1);
+        }
+
+        Ok(Ports {
+            handle: self,
+            info: info
+        })
+    }
+}
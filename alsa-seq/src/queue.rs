@@ -0,0 +1,208 @@
+// Copyright (c) 2015 William Light <wrl@illest.net>
+// 
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// 
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// 
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::ffi;
+use std::ptr::null_mut;
+use std::time::Duration;
+
+use libc::{
+    c_int,
+    c_uint,
+    c_uchar
+};
+
+use alsa_sys::*;
+
+use {
+    SequencerHandle,
+    SequencerQueue,
+    Error
+};
+
+use event::TransliteratedFromCMacros;
+
+const DEFAULT_PPQ: u32 = 96;
+
+// when an event scheduled on a queue should be delivered. ticks are counted at the queue's
+// tempo and PPQ, real time is counted from when the queue was started. the relative variants
+// are counted from the queue's current position instead.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Timestamp {
+    Tick(u32),
+    Real(Duration),
+    RelativeTick(u32),
+    RelativeReal(Duration)
+}
+
+impl Timestamp {
+    pub(crate) fn schedule(self, ev: &mut snd_seq_event_t, queue: c_uchar) {
+        match self {
+            Timestamp::Tick(tick) => ev.schedule_tick(queue, false, tick),
+            Timestamp::RelativeTick(tick) => ev.schedule_tick(queue, true, tick),
+
+            Timestamp::Real(time) =>
+                ev.schedule_real(queue, false, time.as_secs() as c_uint, time.subsec_nanos()),
+            Timestamp::RelativeReal(time) =>
+                ev.schedule_real(queue, true, time.as_secs() as c_uint, time.subsec_nanos())
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct QueueStatus {
+    pub tick: u32,
+    pub time: Duration,
+    pub running: bool,
+
+    // the number of events still waiting to be delivered.
+    pub events: usize
+}
+
+impl SequencerHandle {
+    // the queue starts out stopped, at 120bpm with 96 ticks per quarter note.
+    pub fn create_queue(&self, name: &str) -> Result<SequencerQueue, Error> {
+        let cstr = match ffi::CString::new(name) {
+            Ok(cstr) => cstr,
+            Err(_) => return Err(Error::InvalidName(name.to_string()))
+        };
+
+        let queue = unsafe {
+            match snd_seq_alloc_named_queue(self.raw().0, cstr.as_ptr()) {
+                err_code @ _ if err_code < 0 => return Err(Error::from_code(err_code)),
+                queue_nr => SequencerQueue {
+                    raw_handle: queue_nr,
+                    handle: self.clone()
+                }
+            }
+        };
+
+        queue.set_tempo(120.0, DEFAULT_PPQ)?;
+        Ok(queue)
+    }
+}
+
+impl SequencerQueue {
+    pub fn number(&self) -> c_int {
+        self.raw_handle
+    }
+
+    // queue control events go through the output buffer like any other event, so they're
+    // drained straight away to take effect.
+    fn control(&self, _type: c_uint) -> Result<(), Error> {
+        let raw = self.handle.raw();
+
+        unsafe {
+            match snd_seq_control_queue(raw.0, self.raw_handle, _type as c_int, 0, null_mut()) {
+                err_code @ _ if err_code < 0 => return Err(Error::from_code(err_code)),
+                _ => {}
+            }
+
+            match snd_seq_drain_output(raw.0) {
+                err_code @ _ if err_code < 0 => Err(Error::from_code(err_code)),
+                _ => Ok(())
+            }
+        }
+    }
+
+    // resets the queue's position to zero and starts it running.
+    pub fn start(&self) -> Result<(), Error> {
+        self.control(SND_SEQ_EVENT_START)
+    }
+
+    pub fn stop(&self) -> Result<(), Error> {
+        self.control(SND_SEQ_EVENT_STOP)
+    }
+
+    // starts a stopped queue again from where it was stopped.
+    pub fn resume(&self) -> Result<(), Error> {
+        self.control(SND_SEQ_EVENT_CONTINUE)
+    }
+
+    // `ppq` is the number of ticks per quarter note. alsa only lets the PPQ be changed while
+    // the queue is stopped.
+    pub fn set_tempo(&self, bpm: f32, ppq: u32) -> Result<(), Error> {
+        if bpm <= 0.0 || !bpm.is_finite() {
+            return Err(Error::InvalidTempo(bpm));
+        }
+
+        let mut tempo = null_mut();
+
+        unsafe {
+            match snd_seq_queue_tempo_malloc(&mut tempo) {
+                err_code @ _ if err_code < 0 => return Err(Error::from_code(err_code)),
+                _ => {}
+            }
+
+            // microseconds per quarter note
+            snd_seq_queue_tempo_set_tempo(tempo, (60_000_000.0 / bpm) as c_uint);
+            snd_seq_queue_tempo_set_ppq(tempo, ppq as c_int);
+
+            let err = snd_seq_set_queue_tempo(self.handle.raw().0, self.raw_handle, tempo);
+            snd_seq_queue_tempo_free(tempo);
+
+            match err {
+                err_code @ _ if err_code < 0 => Err(Error::from_code(err_code)),
+                _ => Ok(())
+            }
+        }
+    }
+
+    pub fn status(&self) -> Result<QueueStatus, Error> {
+        let mut status = null_mut();
+
+        unsafe {
+            match snd_seq_queue_status_malloc(&mut status) {
+                err_code @ _ if err_code < 0 => return Err(Error::from_code(err_code)),
+                _ => {}
+            }
+
+            let err = snd_seq_get_queue_status(self.handle.raw().0, self.raw_handle, status);
+
+            let result = match err {
+                err_code @ _ if err_code < 0 => Err(Error::from_code(err_code)),
+                _ => {
+                    let time = &*snd_seq_queue_status_get_real_time(status);
+
+                    Ok(QueueStatus {
+                        tick: snd_seq_queue_status_get_tick_time(status),
+                        time: Duration::new(time.tv_sec as u64, time.tv_nsec),
+                        running: snd_seq_queue_status_get_status(status) & 1 != 0,
+                        events: snd_seq_queue_status_get_events(status).max(0) as usize
+                    })
+                }
+            };
+
+            snd_seq_queue_status_free(status);
+            result
+        }
+    }
+}
+
+impl Drop for SequencerQueue {
+    fn drop(&mut self) {
+        if self.raw_handle < 0 {
+            return;
+        }
+
+        unsafe {
+            snd_seq_free_queue(self.handle.raw().0, self.raw_handle);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright (c) 2015 William Light <wrl@illest.net>
+// 
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+// 
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+// 
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::ptr::null_mut;
+
+use libc::{
+    c_int,
+    ENOENT
+};
+
+use alsa_sys::*;
+
+use {
+    SequencerHandle,
+    SequencerQueue,
+    Address,
+    Error
+};
+
+// a connection from one port to another, with the options that `SequencerPort::connect_to()`
+// and friends leave at their defaults. the sequencer copies it on `subscribe()`, so it can be
+// dropped (or reused) right after.
+pub struct PortSubscription {
+    raw: *mut snd_seq_port_subscribe_t
+}
+
+impl PortSubscription {
+    pub fn new(sender: Address, dest: Address) -> Result<PortSubscription, Error> {
+        let mut raw = null_mut();
+
+        unsafe {
+            match snd_seq_port_subscribe_malloc(&mut raw) {
+                err_code @ _ if err_code < 0 => return Err(Error::from_code(err_code)),
+                _ => {}
+            }
+        }
+
+        let mut sub = PortSubscription {
+            raw: raw
+        };
+
+        sub.set_sender(sender);
+        sub.set_dest(dest);
+        Ok(sub)
+    }
+
+    pub fn sender(&self) -> Address {
+        unsafe {
+            address_from_alsa(snd_seq_port_subscribe_get_sender(self.raw))
+        }
+    }
+
+    pub fn set_sender(&mut self, sender: Address) {
+        unsafe {
+            snd_seq_port_subscribe_set_sender(self.raw, &address_to_alsa(sender));
+        }
+    }
+
+    pub fn dest(&self) -> Address {
+        unsafe {
+            address_from_alsa(snd_seq_port_subscribe_get_dest(self.raw))
+        }
+    }
+
+    pub fn set_dest(&mut self, dest: Address) {
+        unsafe {
+            snd_seq_port_subscribe_set_dest(self.raw, &address_to_alsa(dest));
+        }
+    }
+
+    // an exclusive connection is the only one the sender will take until it's gone.
+    pub fn exclusive(&self) -> bool {
+        unsafe {
+            snd_seq_port_subscribe_get_exclusive(self.raw) != 0
+        }
+    }
+
+    pub fn set_exclusive(&mut self, exclusive: bool) {
+        unsafe {
+            snd_seq_port_subscribe_set_exclusive(self.raw, exclusive as c_int);
+        }
+    }
+
+    // with time updating on, the events delivered through the connection get stamped with
+    // the time of `set_queue()`'s queue on the way, in real time or in ticks as per
+    // `set_time_real()`.
+    pub fn time_update(&self) -> bool {
+        unsafe {
+            snd_seq_port_subscribe_get_time_update(self.raw) != 0
+        }
+    }
+
+    pub fn set_time_update(&mut self, time_update: bool) {
+        unsafe {
+            snd_seq_port_subscribe_set_time_update(self.raw, time_update as c_int);
+        }
+    }
+
+    pub fn time_real(&self) -> bool {
+        unsafe {
+            snd_seq_port_subscribe_get_time_real(self.raw) != 0
+        }
+    }
+
+    pub fn set_time_real(&mut self, time_real: bool) {
+        unsafe {
+            snd_seq_port_subscribe_set_time_real(self.raw, time_real as c_int);
+        }
+    }
+
+    // the number of the queue the timestamps come from.
+    pub fn queue(&self) -> c_int {
+        unsafe {
+            snd_seq_port_subscribe_get_queue(self.raw)
+        }
+    }
+
+    pub fn set_queue(&mut self, queue: &SequencerQueue) {
+        unsafe {
+            snd_seq_port_subscribe_set_queue(self.raw, queue.number());
+        }
+    }
+}
+
+impl Drop for PortSubscription {
+    fn drop(&mut self) {
+        unsafe {
+            snd_seq_port_subscribe_free(self.raw);
+        }
+    }
+}
+
+unsafe fn address_from_alsa(addr: *const snd_seq_addr_t) -> Address {
+    Address {
+        client: (*addr).client,
+        port: (*addr).port
+    }
+}
+
+fn address_to_alsa(addr: Address) -> snd_seq_addr_t {
+    snd_seq_addr_t {
+        client: addr.client,
+        port: addr.port
+    }
+}
+
+impl SequencerHandle {
+    pub fn subscribe(&self, sub: &PortSubscription) -> Result<(), Error> {
+        unsafe {
+            match snd_seq_subscribe_port(self.raw().0, sub.raw) {
+                err_code @ _ if err_code < 0 => Err(Error::from_code(err_code)),
+                _ => Ok(())
+            }
+        }
+    }
+
+    // only the sender and destination of `sub` matter here, the connection goes whatever
+    // options it was made with.
+    pub fn unsubscribe(&self, sub: &PortSubscription) -> Result<(), Error> {
+        unsafe {
+            match snd_seq_unsubscribe_port(self.raw().0, sub.raw) {
+                err_code @ _ if err_code < 0 => Err(Error::from_code(err_code)),
+                _ => Ok(())
+            }
+        }
+    }
+
+    // the connection from `sender` to `dest` as the sequencer has it, or None if the two
+    // aren't connected.
+    pub fn port_subscription(&self, sender: Address, dest: Address)
+        -> Result<Option<PortSubscription>, Error> {
+        let sub = PortSubscription::new(sender, dest)?;
+
+        unsafe {
+            match snd_seq_get_port_subscription(self.raw().0, sub.raw) {
+                err_code @ _ if err_code == -ENOENT => Ok(None),
+                err_code @ _ if err_code < 0 => Err(Error::from_code(err_code)),
+                _ => Ok(Some(sub))
+            }
+        }
+    }
+}
@@ -21,6 +21,15 @@
 #[allow(unused_imports)]
 use super::*;
 
+#[allow(unused_imports)]
+use std::{mem, ptr, slice};
+
+#[allow(unused_imports)]
+use midi::*;
+
+#[allow(unused_imports)]
+use event::*;
+
 #[test]
 fn test_seq_open() {
     let handle = SequencerHandle::open("alsa-seq.rs", HandleOpenStreams::Output).unwrap();
@@ -31,9 +40,458 @@ fn test_seq_create_port() {
     let handle = SequencerHandle::open("alsa-seq.rs", HandleOpenStreams::Output).unwrap();
 
     let port = handle.create_port("test output",
-                                  PORT_CAPABILITY_READ | PORT_CAPABILITY_SUBS_READ,
+                                  PortCapabilities::PORT_CAPABILITY_READ | PortCapabilities::PORT_CAPABILITY_SUBS_READ,
                                   PortType::MidiGeneric).unwrap();
     let port2 = handle.create_port("test output",
-                                  PORT_CAPABILITY_READ | PORT_CAPABILITY_SUBS_READ,
+                                  PortCapabilities::PORT_CAPABILITY_READ | PortCapabilities::PORT_CAPABILITY_SUBS_READ,
+                                  PortType::MidiGeneric).unwrap();
+}
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn test_seq_types_are_send() {
+    assert_send_sync::<SequencerHandle>();
+    assert_send_sync::<SequencerPort>();
+    assert_send_sync::<SequencerQueue>();
+}
+
+#[test]
+fn test_seq_port_outlives_handle() {
+    let port = {
+        let handle = SequencerHandle::open("alsa-seq.rs", HandleOpenStreams::Output).unwrap();
+
+        handle.create_port("test output",
+                           PortCapabilities::PORT_CAPABILITY_READ | PortCapabilities::PORT_CAPABILITY_SUBS_READ,
+                           PortType::MidiGeneric).unwrap()
+    };
+
+    std::thread::spawn(move || {
+        port.send_message(&Message::NoteOn(Channel::Ch1, 60, 100)).unwrap();
+        port.handle().drain_output().unwrap();
+    }).join().unwrap();
+}
+
+#[test]
+fn test_seq_enumerate_ports() {
+    let handle = SequencerHandle::open("alsa-seq.rs enumerate", HandleOpenStreams::Output).unwrap();
+
+    let port = handle.create_port("test output",
+                                  PortCapabilities::PORT_CAPABILITY_READ | PortCapabilities::PORT_CAPABILITY_SUBS_READ,
                                   PortType::MidiGeneric).unwrap();
+
+    let us = handle.clients().unwrap()
+        .find(|client| client.name == "alsa-seq.rs enumerate")
+        .unwrap();
+
+    let ports: Vec<_> = handle.ports(us.number).unwrap().collect();
+    assert_eq!(ports.len(), 1);
+
+    assert_eq!(ports[0].address.port as c_int, port.number());
+    assert_eq!(ports[0].name, "test output");
+    assert!(ports[0].is_readable() && !ports[0].is_writable());
+    assert_eq!(ports[0].types(), vec![PortType::MidiGeneric]);
+}
+
+#[test]
+fn test_seq_loopback() {
+    let handle = SequencerHandle::open("alsa-seq.rs loopback", HandleOpenStreams::Duplex).unwrap();
+
+    let output = handle.create_port("test output",
+                                    PortCapabilities::PORT_CAPABILITY_READ | PortCapabilities::PORT_CAPABILITY_SUBS_READ,
+                                    PortType::MidiGeneric).unwrap();
+    let input = handle.create_port("test input",
+                                   PortCapabilities::PORT_CAPABILITY_WRITE | PortCapabilities::PORT_CAPABILITY_SUBS_WRITE,
+                                   PortType::MidiGeneric).unwrap();
+
+    output.connect_to(Address {
+        client: handle.client_id(),
+        port: input.number() as u8
+    }).unwrap();
+
+    let mut fds = handle.poll_descriptors();
+    assert!(!fds.is_empty());
+
+    output.send_message(&Message::NoteOn(Ch1, 60, 100)).unwrap();
+    output.send_message(&Message::NoteOff(Ch1, 60, 0)).unwrap();
+    handle.drain_output().unwrap();
+
+    unsafe {
+        assert!(libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, 1000) > 0);
+    }
+
+    assert_eq!(handle.recv_event().unwrap(),
+               Some((input.number(), Event::Midi(Message::NoteOn(Ch1, 60, 100)))));
+
+    // both events came in together, so the second one is already waiting.
+    assert_eq!(handle.input_pending(), 1);
+    assert_eq!(handle.recv_event().unwrap(),
+               Some((input.number(), Event::Midi(Message::NoteOff(Ch1, 60, 0)))));
+    assert_eq!(handle.input_pending(), 0);
+}
+
+// skips over whatever else comes in on `handle` until `wanted` does, as long as it's within a
+// second of the last event.
+fn recv_until(handle: &SequencerHandle, wanted: &Event) -> bool {
+    let mut fds = handle.poll_descriptors();
+
+    loop {
+        if handle.input_pending() == 0 {
+            let ready = unsafe {
+                libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, 1000)
+            };
+
+            if ready <= 0 {
+                return false;
+            }
+        }
+
+        match handle.recv_event().unwrap() {
+            Some((_, ref event)) if event == wanted => return true,
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_seq_announcements() {
+    let handle = SequencerHandle::open("alsa-seq.rs announcements", HandleOpenStreams::Input).unwrap();
+    let _announcements = handle.subscribe_announcements().unwrap();
+
+    let other = SequencerHandle::open("alsa-seq.rs", HandleOpenStreams::Output).unwrap();
+    let port = other.create_port("test output",
+                                 PortCapabilities::PORT_CAPABILITY_READ | PortCapabilities::PORT_CAPABILITY_SUBS_READ,
+                                 PortType::MidiGeneric).unwrap();
+
+    let address = Address {
+        client: other.client_id(),
+        port: port.number() as u8
+    };
+
+    assert!(recv_until(&handle, &Event::PortStart(address)));
+
+    drop(port);
+    assert!(recv_until(&handle, &Event::PortExit(address)));
+}
+
+#[test]
+fn test_seq_output_buffer() {
+    let handle = SequencerHandle::open("alsa-seq.rs output buffer", HandleOpenStreams::Duplex).unwrap();
+
+    handle.set_output_buffer_size(4096).unwrap();
+    assert_eq!(handle.output_buffer_size(), 4096);
+
+    let output = handle.create_port("test output",
+                                    PortCapabilities::PORT_CAPABILITY_READ | PortCapabilities::PORT_CAPABILITY_SUBS_READ,
+                                    PortType::MidiGeneric).unwrap();
+    let input = handle.create_port("test input",
+                                   PortCapabilities::PORT_CAPABILITY_WRITE | PortCapabilities::PORT_CAPABILITY_SUBS_WRITE,
+                                   PortType::MidiGeneric).unwrap();
+
+    output.connect_to(Address {
+        client: handle.client_id(),
+        port: input.number() as u8
+    }).unwrap();
+
+    // nothing goes out until the buffer is drained.
+    output.send_message(&Message::NoteOn(Ch1, 60, 100)).unwrap();
+    assert!(handle.output_pending() > 0);
+
+    assert_eq!(handle.drain_output().unwrap(), 0);
+    assert_eq!(handle.output_pending(), 0);
+
+    assert!(recv_until(&handle, &Event::Midi(Message::NoteOn(Ch1, 60, 100))));
+}
+
+#[test]
+fn test_seq_port_subscription() {
+    let handle = SequencerHandle::open("alsa-seq.rs subscription", HandleOpenStreams::Duplex).unwrap();
+
+    let output = handle.create_port("test output",
+                                    PortCapabilities::PORT_CAPABILITY_READ | PortCapabilities::PORT_CAPABILITY_SUBS_READ,
+                                    PortType::MidiGeneric).unwrap();
+    let input = handle.create_port("test input",
+                                   PortCapabilities::PORT_CAPABILITY_WRITE | PortCapabilities::PORT_CAPABILITY_SUBS_WRITE,
+                                   PortType::MidiGeneric).unwrap();
+
+    let sender = Address { client: handle.client_id(), port: output.number() as u8 };
+    let dest = Address { client: handle.client_id(), port: input.number() as u8 };
+
+    assert!(handle.port_subscription(sender, dest).unwrap().is_none());
+
+    let mut sub = PortSubscription::new(sender, dest).unwrap();
+    sub.set_exclusive(true);
+    handle.subscribe(&sub).unwrap();
+
+    let queried = handle.port_subscription(sender, dest).unwrap().unwrap();
+    assert_eq!((queried.sender(), queried.dest()), (sender, dest));
+    assert!(queried.exclusive());
+    assert!(!queried.time_update());
+
+    // the sender's taken while an exclusive connection is up.
+    assert!(output.connect_to(dest).is_err());
+
+    handle.unsubscribe(&sub).unwrap();
+    assert!(handle.port_subscription(sender, dest).unwrap().is_none());
+}
+
+#[test]
+fn test_seq_queue_tempo() {
+    let handle = SequencerHandle::open("alsa-seq.rs", HandleOpenStreams::Output).unwrap();
+    let queue = handle.create_queue("test queue").unwrap();
+
+    queue.set_tempo(90.0, 96).unwrap();
+
+    match queue.set_tempo(0.0, 96) {
+        Err(Error::InvalidTempo(bpm)) => assert_eq!(bpm, 0.0),
+        _ => panic!("expected an invalid tempo error")
+    }
+}
+
+#[test]
+fn test_error_messages() {
+    let err = Error::from_code(-libc::ENOENT);
+    assert_eq!(format!("{}", err), "No such file or directory (alsa error -2)");
+
+    match SequencerHandle::open("bad\0name", HandleOpenStreams::Output) {
+        Err(Error::InvalidName(name)) => assert_eq!(name, "bad\0name"),
+        _ => panic!("expected an invalid name error")
+    }
+
+    assert_eq!(format!("{}", Error::InvalidTempo(-1.0)), "invalid tempo -1 bpm");
+}
+
+//
+// ToSndSeqEvent, checked byte-for-byte against what alsa's snd_seq_ev_set_*() macros leave in
+// a zeroed event
+//
+
+fn event_bytes(ev: &snd_seq_event_t) -> Vec<u8> {
+    unsafe {
+        slice::from_raw_parts(ev as *const snd_seq_event_t as *const u8,
+                              mem::size_of::<snd_seq_event_t>()).to_vec()
+    }
+}
+
+fn assert_encodes_to(msg: Message, expected: snd_seq_event_t) {
+    let ev = msg.to_snd_seq_event(&mut Vec::new()).unwrap();
+    assert_eq!(event_bytes(&ev), event_bytes(&expected), "{:?}", msg);
+}
+
+// snd_seq_ev_set_noteon() and friends
+fn note_event(_type: c_uint, channel: u8, note: u8, velocity: u8) -> snd_seq_event_t {
+    let mut ev = empty_event();
+    ev._type = _type as snd_seq_event_type_t;
+
+    unsafe {
+        let data = ev.data.note();
+        (*data).channel = channel;
+        (*data).note = note;
+        (*data).velocity = velocity;
+    }
+
+    ev
+}
+
+// snd_seq_ev_set_controller(), _pgmchange(), _chanpress() and _pitchbend()
+fn control_event(_type: c_uint, channel: u8, param: c_uint, value: c_int) -> snd_seq_event_t {
+    let mut ev = empty_event();
+    ev._type = _type as snd_seq_event_type_t;
+
+    unsafe {
+        let data = ev.data.control();
+        (*data).channel = channel;
+        (*data).param = param;
+        (*data).value = value;
+    }
+
+    ev
+}
+
+fn realtime_event(_type: c_uint) -> snd_seq_event_t {
+    let mut ev = empty_event();
+    ev._type = _type as snd_seq_event_type_t;
+    ev
+}
+
+#[test]
+fn test_encode_notes() {
+    assert_encodes_to(Message::NoteOn(Ch1, 60, 100), note_event(SND_SEQ_EVENT_NOTEON, 0, 60, 100));
+    assert_encodes_to(Message::NoteOff(Ch16, 60, 0), note_event(SND_SEQ_EVENT_NOTEOFF, 15, 60, 0));
+    assert_encodes_to(Message::PolyphonicPressure(Ch10, 36, 12),
+                      note_event(SND_SEQ_EVENT_KEYPRESS, 9, 36, 12));
+}
+
+#[test]
+fn test_encode_controllers() {
+    assert_encodes_to(Message::ControlChange(Ch2, 7, 127),
+                      control_event(SND_SEQ_EVENT_CONTROLLER, 1, 7, 127));
+    assert_encodes_to(Message::AllSoundOff(Ch1), control_event(SND_SEQ_EVENT_CONTROLLER, 0, 120, 0));
+    assert_encodes_to(Message::ResetAllControllers(Ch1),
+                      control_event(SND_SEQ_EVENT_CONTROLLER, 0, 121, 0));
+    assert_encodes_to(Message::LocalControlOff(Ch1),
+                      control_event(SND_SEQ_EVENT_CONTROLLER, 0, 122, 0));
+    assert_encodes_to(Message::LocalControlOn(Ch1),
+                      control_event(SND_SEQ_EVENT_CONTROLLER, 0, 122, 127));
+    assert_encodes_to(Message::AllNotesOff(Ch3), control_event(SND_SEQ_EVENT_CONTROLLER, 2, 123, 0));
+}
+
+#[test]
+fn test_encode_channel_messages() {
+    assert_encodes_to(Message::ProgramChange(Ch5, 42),
+                      control_event(SND_SEQ_EVENT_PGMCHANGE, 4, 0, 42));
+    assert_encodes_to(Message::ChannelPressure(Ch1, 99),
+                      control_event(SND_SEQ_EVENT_CHANPRESS, 0, 0, 99));
+
+    assert_encodes_to(Message::PitchBend(Ch1, 0x2000),
+                      control_event(SND_SEQ_EVENT_PITCHBEND, 0, 0, 0));
+    assert_encodes_to(Message::PitchBend(Ch1, 0x0000),
+                      control_event(SND_SEQ_EVENT_PITCHBEND, 0, 0, -8192));
+    assert_encodes_to(Message::PitchBend(Ch1, 0x3FFF),
+                      control_event(SND_SEQ_EVENT_PITCHBEND, 0, 0, 8191));
+
+    // the (N)RPNs are several controller changes, which one event can't carry.
+    assert!(Message::RPN7(Ch1, 0, 2).to_snd_seq_event(&mut Vec::new()).is_none());
+    assert!(Message::NRPN14(Ch1, 1000, 1001).to_snd_seq_event(&mut Vec::new()).is_none());
+}
+
+#[test]
+fn test_encode_realtime() {
+    assert_encodes_to(Message::TimingClock, realtime_event(SND_SEQ_EVENT_CLOCK));
+    assert_encodes_to(Message::Start, realtime_event(SND_SEQ_EVENT_START));
+    assert_encodes_to(Message::Continue, realtime_event(SND_SEQ_EVENT_CONTINUE));
+    assert_encodes_to(Message::Stop, realtime_event(SND_SEQ_EVENT_STOP));
+    assert_encodes_to(Message::ActiveSensing, realtime_event(SND_SEQ_EVENT_SENSING));
+    assert_encodes_to(Message::SystemReset, realtime_event(SND_SEQ_EVENT_RESET));
+}
+
+#[test]
+fn test_encode_sysex() {
+    let sysex = [0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7];
+
+    let mut ev = empty_event();
+    ev.set_sysex(sysex.len() as c_uint, sysex.as_ptr() as *mut c_void);
+
+    // snd_seq_ev_set_sysex()
+    let mut expected = empty_event();
+    expected._type = SND_SEQ_EVENT_SYSEX as snd_seq_event_type_t;
+    expected.flags = 1 << 2;
+
+    // the packed snd_seq_ev_ext_t: the length, then the pointer straight after it
+    unsafe {
+        let ext = &mut expected.data as *mut _ as *mut u8;
+        ptr::write_unaligned(ext as *mut c_uint, sysex.len() as c_uint);
+        ptr::write_unaligned(ext.offset(4) as *mut *const u8, sysex.as_ptr());
+    }
+
+    assert_eq!(event_bytes(&ev), event_bytes(&expected));
+
+    // a Message::SysEx comes out the same, with the message put together in `data`.
+    let msg = Message::SysEx(Manufacturer::OneByte(0x7E), vec![0x7F, 0x06, 0x01]);
+    let mut data = Vec::new();
+    let ev = msg.to_snd_seq_event(&mut data).unwrap();
+
+    assert_eq!(data, sysex.to_vec());
+
+    unsafe {
+        let ext = &mut expected.data as *mut _ as *mut u8;
+        ptr::write_unaligned(ext.offset(4) as *mut *const u8, data.as_ptr());
+    }
+
+    assert_eq!(event_bytes(&ev), event_bytes(&expected));
+}
+
+#[test]
+fn test_schedule() {
+    let mut ev = empty_event();
+
+    Timestamp::Tick(480).schedule(&mut ev, 3);
+    assert_eq!((ev.flags, ev.queue, ev.time.data), (0, 3, [480, 0]));
+
+    Timestamp::RelativeReal(std::time::Duration::new(2, 500)).schedule(&mut ev, 3);
+    assert_eq!((ev.flags, ev.queue, ev.time.data), (3, 3, [2, 500]));
+
+    // scheduling leaves the event's length flags alone
+    let mut ev = Message::NoteOn(Channel::Ch1, 60, 100).to_snd_seq_event(&mut Vec::new()).unwrap();
+    let flags = ev.flags;
+
+    Timestamp::Real(std::time::Duration::from_millis(1500)).schedule(&mut ev, 0);
+    assert_eq!((ev.flags, ev.queue, ev.time.data), (flags | 1, 0, [1, 500_000_000]));
+}
+
+//
+// FromSndSeqEvent
+//
+
+#[test]
+fn test_decode_roundtrip() {
+    let messages = [
+        Message::NoteOn(Ch1, 60, 100),
+        Message::NoteOff(Ch16, 60, 64),
+        Message::PolyphonicPressure(Ch10, 36, 12),
+        Message::ControlChange(Ch2, 7, 127),
+        Message::AllSoundOff(Ch1),
+        Message::ResetAllControllers(Ch1),
+        Message::LocalControlOff(Ch1),
+        Message::LocalControlOn(Ch1),
+        Message::AllNotesOff(Ch3),
+        Message::ProgramChange(Ch5, 42),
+        Message::ChannelPressure(Ch1, 99),
+        Message::PitchBend(Ch1, 0x2000),
+        Message::PitchBend(Ch1, 0x0000),
+        Message::PitchBend(Ch1, 0x3FFF),
+        Message::TimingClock,
+        Message::Start,
+        Message::Continue,
+        Message::Stop,
+        Message::ActiveSensing,
+        Message::SystemReset
+    ];
+
+    for msg in messages.iter() {
+        let ev = msg.to_snd_seq_event(&mut Vec::new()).unwrap();
+        assert_eq!(Event::from_snd_seq_event(&ev), Event::Midi(msg.clone()));
+    }
+}
+
+#[test]
+fn test_decode_sysex() {
+    let sysex = [0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7];
+
+    let mut ev = empty_event();
+    ev.set_sysex(sysex.len() as c_uint, sysex.as_ptr() as *mut c_void);
+
+    assert_eq!(Event::from_snd_seq_event(&ev), Event::SysEx(sysex.to_vec()));
+}
+
+#[test]
+fn test_decode_announcements() {
+    let mut ev = empty_event();
+    ev._type = SND_SEQ_EVENT_PORT_START as snd_seq_event_type_t;
+
+    unsafe {
+        let addr = ev.data.addr();
+        (*addr).client = 128;
+        (*addr).port = 2;
+    }
+
+    assert_eq!(Event::from_snd_seq_event(&ev),
+               Event::PortStart(Address { client: 128, port: 2 }));
+
+    ev._type = SND_SEQ_EVENT_ECHO as snd_seq_event_type_t;
+    assert_eq!(Event::from_snd_seq_event(&ev), Event::Other(SND_SEQ_EVENT_ECHO as u8));
+}
+
+#[test]
+fn test_decode_note_and_control14() {
+    let mut ev = empty_event();
+    ev.set_note(SND_SEQ_EVENT_NOTE, Ch2, 60, 100, 480);
+    assert_eq!(Event::from_snd_seq_event(&ev), Event::Midi(Message::NoteOn(Ch2, 60, 100)));
+
+    ev.set_controller(SND_SEQ_EVENT_CONTROL14, Ch3, 7, 0x2001);
+    assert_eq!(Event::from_snd_seq_event(&ev), Event::ControlChange14(Ch3, 7, 0x2001));
+
+    // only controllers 0-31 have an LSB controller to go with them
+    ev.set_controller(SND_SEQ_EVENT_CONTROL14, Ch3, 64, 127);
+    assert_eq!(Event::from_snd_seq_event(&ev), Event::Midi(Message::ControlChange(Ch3, 64, 127)));
 }
@@ -0,0 +1,190 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use midi::{
+    Channel,
+    U7
+};
+
+use config::{
+    Config,
+    Entry,
+    parse_color
+};
+
+use sink::channel_from_number;
+
+use encoder::{
+    EncoderCc,
+    EncoderMode,
+    parse_encoder_mode
+};
+
+pub const BANK_COUNT: usize = 8;
+
+pub const BANK_NAMES: [&str; BANK_COUNT] = ["A", "B", "C", "D", "E", "F", "G", "H"];
+
+const DEFAULT_NOTE_BASE: U7 = 48;
+
+// red, orange, yellow, green, cyan, blue, purple, pink.
+const DEFAULT_COLORS: [u32; BANK_COUNT] = [
+    0x990000, 0x994C00, 0x999900, 0x009900,
+    0x009999, 0x003399, 0x4C0099, 0x990066
+];
+
+// a set of pad settings, like one of maschine's groups. hold group and hit pad 1-8 to pick
+// one.
+#[derive(Clone, Debug)]
+pub struct Bank {
+    pub note_base: U7,
+
+    // the bank's pads play on their own channel if they have one, on the bank's if not, and
+    // on the global channel if the bank hasn't got one either.
+    pub channel: Option<Channel>,
+    pub pad_channels: [Option<Channel>; 16],
+
+    pub color: u32,
+
+    // the encoder sends this CC (on the bank's channel) while the bank is active.
+    pub encoder: Option<EncoderCc>
+}
+
+impl Bank {
+    pub fn pad_channel(&self, pad_idx: usize, global: Channel) -> Channel {
+        self.pad_channels[pad_idx].or(self.channel).unwrap_or(global)
+    }
+}
+
+pub fn default_banks() -> Vec<Bank> {
+    DEFAULT_COLORS.iter()
+        .map(|&color| Bank {
+            note_base: DEFAULT_NOTE_BASE,
+            channel: None,
+            pad_channels: [None; 16],
+            color: color,
+            encoder: None
+        })
+        .collect()
+}
+
+pub fn bank_by_name(name: &str) -> Option<usize> {
+    BANK_NAMES.iter().position(|&n| n.eq_ignore_ascii_case(name))
+}
+
+fn parse_channel(entry: &Entry, value: &str) -> Result<Channel, String> {
+    match value.parse().ok().and_then(channel_from_number) {
+        Some(channel) => Ok(channel),
+        None => Err(format!("line {}: bad MIDI channel {}", entry.line, value))
+    }
+}
+
+// 16 channels or "-" for none, left to right, top row first.
+fn parse_pad_channels(entry: &Entry) -> Result<[Option<Channel>; 16], String> {
+    let values: Vec<_> = entry.value.split_whitespace().collect();
+
+    if values.len() != 16 {
+        return Err(format!("line {}: {} needs 16 channels, not {}", entry.line, entry.key,
+                           values.len()));
+    }
+
+    let mut channels = [None; 16];
+
+    for (channel, value) in channels.iter_mut().zip(values.iter()) {
+        if *value != "-" {
+            *channel = Some(parse_channel(entry, value)?);
+        }
+    }
+
+    Ok(channels)
+}
+
+// the channel for the pads of any bank which doesn't say otherwise, from a top-level
+// "channel = ..." in the config file.
+pub fn global_channel_from_config(config: &Config) -> Result<Channel, String> {
+    match config.global().get("channel") {
+        Some(entry) => parse_channel(entry, &entry.value),
+        None => Ok(Channel::Ch1)
+    }
+}
+
+// [bank <A-H>] sections, each of them optional:
+//
+//     note_base = <the note the bottom left pad plays>
+//     channel = <1-16>
+//     pad_channels = <16 channels or "-", left to right, top row first>
+//     color = <rrggbb>
+//     encoder_cc = <the controller number the encoder sends, if it sends one>
+//     encoder_mode = <absolute, twos_complement, sign_magnitude or binary_offset>
+//     encoder_min = <the bottom of the range in absolute mode, defaults to 0>
+//     encoder_max = <the top of it, defaults to 127>
+pub fn banks_from_config(config: &Config) -> Result<Vec<Bank>, String> {
+    let mut banks = default_banks();
+
+    for section in config.sections("bank") {
+        let bank = match section.name.as_ref().and_then(|name| bank_by_name(name)) {
+            Some(idx) => &mut banks[idx],
+            None => return Err(format!("line {}: banks are called A to H", section.line))
+        };
+
+        let mut encoder_cc = None;
+        let mut encoder_mode = EncoderMode::Absolute { min: 0, max: 127 };
+        let mut encoder_range = (0, 127);
+
+        let parse_u7 = |entry: &Entry| match entry.value.parse::<U7>() {
+            Ok(value) if value <= 127 => Ok(value),
+            _ => Err(format!("line {}: bad {} {}", entry.line, entry.key, entry.value))
+        };
+
+        for entry in section.entries.iter() {
+            match &*entry.key {
+                "encoder_cc" => encoder_cc = Some(parse_u7(entry)?),
+                "encoder_mode" => encoder_mode = parse_encoder_mode(entry)?,
+                "encoder_min" => encoder_range.0 = parse_u7(entry)?,
+                "encoder_max" => encoder_range.1 = parse_u7(entry)?,
+
+                "note_base" => bank.note_base = match entry.value.parse::<U7>() {
+                    Ok(base) if base <= 127 => base,
+                    _ => return Err(format!("line {}: bad note base {}", entry.line,
+                                            entry.value))
+                },
+
+                "channel" => bank.channel = Some(parse_channel(entry, &entry.value)?),
+                "pad_channels" => bank.pad_channels = parse_pad_channels(entry)?,
+
+                "color" => bank.color = parse_color(entry, &entry.value)?,
+
+                _ => return Err(format!("line {}: unknown bank setting {}", entry.line,
+                                        entry.key))
+            }
+        }
+
+        if let EncoderMode::Absolute { .. } = encoder_mode {
+            if encoder_range.0 > encoder_range.1 {
+                return Err(format!("line {}: encoder_min is above encoder_max", section.line));
+            }
+
+            encoder_mode = EncoderMode::Absolute {
+                min: encoder_range.0,
+                max: encoder_range.1
+            };
+        }
+
+        bank.encoder = encoder_cc.map(|controller| EncoderCc::new(controller, encoder_mode));
+    }
+
+    Ok(banks)
+}
@@ -0,0 +1,138 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use std::time::{
+    Duration,
+    Instant
+};
+
+// pads are indexed row-major from the top left of a 4x4 grid, which is the same layout that
+// the note map in main.rs is written out in.
+const PAD_GRID_WIDTH: usize = 4;
+const PAD_COUNT: usize = PAD_GRID_WIDTH * PAD_GRID_WIDTH;
+
+const DEFAULT_RATIO: f32 = 0.2;
+const DEFAULT_WINDOW_MS: u64 = 10;
+
+#[derive(Copy, Clone, Debug)]
+struct Onset {
+    time: Instant,
+    peak: f32
+}
+
+// hitting a pad hard makes the pads physically next to it register a little pressure too.
+// an onset on a pad is rejected when a neighbouring pad started within `window` of it and
+// the neighbour's peak pressure, scaled by `ratio`, is still stronger than this pad's.
+pub struct CrosstalkFilter {
+    ratio: f32,
+    window: Duration,
+
+    onsets: [Option<Onset>; PAD_COUNT],
+    suppressed: [bool; PAD_COUNT]
+}
+
+impl Default for CrosstalkFilter {
+    fn default() -> Self {
+        CrosstalkFilter {
+            ratio: DEFAULT_RATIO,
+            window: Duration::from_millis(DEFAULT_WINDOW_MS),
+
+            onsets: [None; PAD_COUNT],
+            suppressed: [false; PAD_COUNT]
+        }
+    }
+}
+
+fn time_between(a: Instant, b: Instant) -> Duration {
+    if a >= b {
+        a.duration_since(b)
+    } else {
+        b.duration_since(a)
+    }
+}
+
+// pads touching `a` along a side or at a corner.
+pub fn are_neighbours(a: usize, b: usize) -> bool {
+    let (a_row, a_col) = ((a / PAD_GRID_WIDTH) as isize, (a % PAD_GRID_WIDTH) as isize);
+    let (b_row, b_col) = ((b / PAD_GRID_WIDTH) as isize, (b % PAD_GRID_WIDTH) as isize);
+
+    a != b && (a_row - b_row).abs() <= 1 && (a_col - b_col).abs() <= 1
+}
+
+impl CrosstalkFilter {
+    // a ratio of 0.0 disables the filter.
+    pub fn configure(&mut self, ratio: f32, window: Duration) {
+        self.ratio = ratio;
+        self.window = window;
+    }
+
+    // called for every pad which crossed the threshold in a report, before any of them are
+    // checked with `is_crosstalk()`, so that hits landing in the same report see each other.
+    pub fn onset(&mut self, pad_idx: usize, pressure: f32, time: Instant) {
+        self.onsets[pad_idx] = Some(Onset {
+            time: time,
+            peak: pressure
+        });
+    }
+
+    // tracks how hard a pad was actually hit, since the first report after an onset rarely
+    // carries the full pressure.
+    pub fn aftertouch(&mut self, pad_idx: usize, pressure: f32, time: Instant) {
+        let window = self.window;
+
+        if let Some(ref mut onset) = self.onsets[pad_idx] {
+            if time_between(time, onset.time) <= window && pressure > onset.peak {
+                onset.peak = pressure;
+            }
+        }
+    }
+
+    pub fn released(&mut self, pad_idx: usize) {
+        self.onsets[pad_idx] = None;
+        self.suppressed[pad_idx] = false;
+    }
+
+    // decides whether a fresh onset is a ghost hit. once rejected, a pad stays suppressed
+    // until it's released, so that its aftertouch and release are swallowed as well.
+    pub fn is_crosstalk(&mut self, pad_idx: usize, time: Instant) -> bool {
+        if self.ratio <= 0.0 {
+            return false;
+        }
+
+        let pressure = match self.onsets[pad_idx] {
+            Some(onset) => onset.peak,
+            None => return false
+        };
+
+        let rejected = (0..PAD_COUNT)
+            .filter(|&other| are_neighbours(pad_idx, other) && !self.suppressed[other])
+            .filter_map(|other| self.onsets[other])
+            .any(|onset| time_between(time, onset.time) <= self.window
+                 && pressure < onset.peak * self.ratio);
+
+        if rejected {
+            self.onsets[pad_idx] = None;
+            self.suppressed[pad_idx] = true;
+        }
+
+        rejected
+    }
+
+    pub fn is_suppressed(&self, pad_idx: usize) -> bool {
+        self.suppressed[pad_idx]
+    }
+}
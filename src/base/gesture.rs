@@ -0,0 +1,175 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use std::time::{
+    Duration,
+    Instant
+};
+
+use base::{
+    Maschine,
+    MaschineHandler,
+    MaschineButton
+};
+
+use base::maschine::{
+    ALL_BUTTONS,
+    BUTTON_COUNT
+};
+
+// the buttons which make combos with the other buttons and the pads.
+pub const MODIFIERS: [MaschineButton; 2] = [MaschineButton::Shift, MaschineButton::Group];
+
+const DEFAULT_LONG_PRESS_MS: u64 = 500;
+const DEFAULT_DOUBLE_PRESS_MS: u64 = 300;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Gesture {
+    LongPress(MaschineButton),
+    DoublePress(MaschineButton),
+    ButtonCombo(MaschineButton, MaschineButton),
+    PadCombo(MaschineButton, usize, f32)
+}
+
+impl Gesture {
+    pub fn dispatch(self, maschine: &mut dyn Maschine, handler: &mut dyn MaschineHandler,
+                    time: Instant) {
+        match self {
+            Gesture::LongPress(btn) =>
+                handler.button_long_press(maschine, btn, time),
+
+            Gesture::DoublePress(btn) =>
+                handler.button_double_press(maschine, btn, time),
+
+            Gesture::ButtonCombo(modifier, btn) =>
+                handler.button_combo(maschine, modifier, btn, time),
+
+            Gesture::PadCombo(modifier, pad_idx, pressure) =>
+                handler.pad_combo(maschine, modifier, pad_idx, pressure, time)
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct HeldButton {
+    since: Instant,
+
+    // a button which has already fired a long press, or which was used as the modifier in a
+    // combo, won't fire a long press (again).
+    consumed: bool
+}
+
+// turns the raw button and pad stream into higher-level gestures. the device feeds it every
+// button and pad event it passes on to the handler, and calls `tick()` periodically so that
+// long presses fire while the button is still held.
+pub struct ButtonGestures {
+    long_press: Duration,
+    double_press: Duration,
+
+    modifiers: Vec<MaschineButton>,
+
+    held: [Option<HeldButton>; BUTTON_COUNT],
+    last_press: [Option<Instant>; BUTTON_COUNT]
+}
+
+impl Default for ButtonGestures {
+    fn default() -> Self {
+        ButtonGestures {
+            long_press: Duration::from_millis(DEFAULT_LONG_PRESS_MS),
+            double_press: Duration::from_millis(DEFAULT_DOUBLE_PRESS_MS),
+
+            modifiers: MODIFIERS.to_vec(),
+
+            held: [None; BUTTON_COUNT],
+            last_press: [None; BUTTON_COUNT]
+        }
+    }
+}
+
+impl ButtonGestures {
+    pub fn set_timings(&mut self, long_press: Duration, double_press: Duration) {
+        self.long_press = long_press;
+        self.double_press = double_press;
+    }
+
+    // every modifier that's currently held down. they're marked as consumed, since being
+    // part of a combo means they shouldn't also fire a long press.
+    fn held_modifiers(&mut self) -> Vec<MaschineButton> {
+        let mut held_modifiers = Vec::new();
+
+        for &modifier in self.modifiers.iter() {
+            if let Some(ref mut held) = self.held[modifier as usize] {
+                held.consumed = true;
+                held_modifiers.push(modifier);
+            }
+        }
+
+        held_modifiers
+    }
+
+    pub fn button_down(&mut self, btn: MaschineButton, time: Instant) -> Vec<Gesture> {
+        let mut gestures: Vec<_> = self.held_modifiers().into_iter()
+            .filter(|&modifier| modifier != btn)
+            .map(|modifier| Gesture::ButtonCombo(modifier, btn))
+            .collect();
+
+        let idx = btn as usize;
+
+        match self.last_press[idx] {
+            Some(last) if time.duration_since(last) <= self.double_press => {
+                gestures.push(Gesture::DoublePress(btn));
+
+                // so that a triple press doesn't count as two doubles.
+                self.last_press[idx] = None;
+            },
+
+            _ => self.last_press[idx] = Some(time)
+        }
+
+        self.held[idx] = Some(HeldButton {
+            since: time,
+            consumed: false
+        });
+
+        gestures
+    }
+
+    pub fn button_up(&mut self, btn: MaschineButton) {
+        self.held[btn as usize] = None;
+    }
+
+    pub fn pad_pressed(&mut self, pad_idx: usize, pressure: f32) -> Vec<Gesture> {
+        self.held_modifiers().into_iter()
+            .map(|modifier| Gesture::PadCombo(modifier, pad_idx, pressure))
+            .collect()
+    }
+
+    pub fn tick(&mut self, time: Instant) -> Vec<Gesture> {
+        let mut gestures = Vec::new();
+
+        for (idx, held) in self.held.iter_mut().enumerate() {
+            if let Some(ref mut held) = *held {
+                if !held.consumed && time.duration_since(held.since) >= self.long_press {
+                    held.consumed = true;
+                    gestures.push(Gesture::LongPress(ALL_BUTTONS[idx]));
+                }
+            }
+        }
+
+        gestures
+    }
+}
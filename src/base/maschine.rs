@@ -15,10 +15,31 @@
 //  License along with this program.  If not, see
 //  <http://www.gnu.org/licenses/>.
 
+use std::io;
 use std::os::unix::io::RawFd;
+use std::time::{
+    Duration,
+    Instant
+};
 
-#[derive(Copy,Clone,Debug)]
-pub enum MaschineButton {
+// the buttons are only listed once, below, which gives both the enum and `ALL_BUTTONS`, so
+// that nothing going through every button can miss a new one.
+macro_rules! maschine_buttons {
+    ($($button:ident),*) => {
+        #[derive(Copy,Clone,Debug,PartialEq,Eq)]
+        pub enum MaschineButton {
+            $($button),*
+        }
+
+        // every button, in the order they're declared in, so `ALL_BUTTONS[btn as usize]`
+        // is `btn`.
+        pub const ALL_BUTTONS: &[MaschineButton] = &[
+            $(MaschineButton::$button),*
+        ];
+    }
+}
+
+maschine_buttons! {
     Restart,
     StepLeft,
     StepRight,
@@ -54,6 +75,8 @@ pub enum MaschineButton {
     Mute
 }
 
+pub const BUTTON_COUNT: usize = ALL_BUTTONS.len();
+
 pub trait Maschine {
     fn get_fd(&self) -> RawFd;
 
@@ -62,23 +85,59 @@ pub trait Maschine {
     fn get_midi_note_base(&self) -> u8;
     fn set_midi_note_base(&mut self, base: u8);
 
+    fn set_crosstalk_rejection(&mut self, ratio: f32, window: Duration);
+    fn set_gesture_timings(&mut self, long_press: Duration, double_press: Duration);
+
     fn set_pad_light(&mut self, pad_idx: usize, color: u32, brightness: f32);
     fn set_button_light(&mut self, btn: MaschineButton, color: u32, brightness: f32);
 
-    fn readable(&mut self, &mut dyn MaschineHandler);
+    // an error means the device can't be read any more, most likely because it was unplugged.
+    fn readable(&mut self, &mut dyn MaschineHandler) -> io::Result<()>;
+
+    // called periodically from the event loop, for things which happen with the passage of
+    // time rather than in response to a report (long presses, for example).
+    fn tick(&mut self, &mut dyn MaschineHandler);
+
+    // `line` is one of the screen's rows of text, from the top. the text replaces whatever
+    // was on the line, and shows up with the next `write_screen()`.
+    fn set_screen_text(&mut self, line: usize, text: &str);
 
     fn clear_screen(&mut self);
-    fn write_lights(&mut self);
+    fn write_screen(&mut self) -> io::Result<()>;
+    fn write_lights(&mut self) -> io::Result<()>;
 }
 
 #[allow(unused_variables)]
 pub trait MaschineHandler {
-    fn pad_pressed(&mut self, &mut dyn Maschine, pad_idx: usize, pressure: f32) {}
-    fn pad_aftertouch(&mut self, &mut dyn Maschine, pad_idx: usize, pressure: f32) {}
-    fn pad_released(&mut self, &mut dyn Maschine, pad_idx: usize) {}
+    // `time` is always the moment the device report which caused the event was read (or,
+    // for long presses, when the press was noticed) and is monotonic.
+
+    fn pad_pressed(&mut self, &mut dyn Maschine, pad_idx: usize, pressure: f32, time: Instant) {}
+    fn pad_aftertouch(&mut self, &mut dyn Maschine, pad_idx: usize, pressure: f32,
+                      time: Instant) {}
+    fn pad_released(&mut self, &mut dyn Maschine, pad_idx: usize, time: Instant) {}
+
+    // unfiltered 12-bit pad values, one slice per report from the device.
+    fn pads_raw(&mut self, &mut dyn Maschine, values: &[u16], time: Instant) {}
+
+    fn encoder_step(&mut self, &mut dyn Maschine, encoder_idx: usize, delta: i32,
+                    time: Instant) {}
+
+    fn button_down(&mut self, &mut dyn Maschine, button: MaschineButton, time: Instant) {}
+    fn button_up(&mut self, &mut dyn Maschine, button: MaschineButton, time: Instant) {}
+
+    fn button_long_press(&mut self, &mut dyn Maschine, button: MaschineButton, time: Instant) {}
+    fn button_double_press(&mut self, &mut dyn Maschine, button: MaschineButton,
+                           time: Instant) {}
 
-    fn encoder_step(&mut self, &mut dyn Maschine, encoder_idx: usize, delta: i32) {}
+    // `modifier` is held down (shift, for example) while `button` or a pad is pressed. these
+    // come in addition to the regular button_down()/pad_pressed() calls.
+    fn button_combo(&mut self, &mut dyn Maschine, modifier: MaschineButton,
+                    button: MaschineButton, time: Instant) {}
+    fn pad_combo(&mut self, &mut dyn Maschine, modifier: MaschineButton, pad_idx: usize,
+                 pressure: f32, time: Instant) {}
 
-    fn button_down(&mut self, &mut dyn Maschine, button: MaschineButton) {}
-    fn button_up(&mut self, &mut dyn Maschine, button: MaschineButton) {}
+    // called once all the events from a report have been dispatched, so that whatever they
+    // produced can be sent off in one go.
+    fn report_processed(&mut self, &mut dyn Maschine, time: Instant) {}
 }
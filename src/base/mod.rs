@@ -27,3 +27,11 @@ pub use self::maschine_pad::{
     MaschinePad,
     MaschinePadStateTransition
 };
+
+pub mod crosstalk;
+pub use self::crosstalk::CrosstalkFilter;
+
+pub mod gesture;
+pub use self::gesture::ButtonGestures;
+
+pub mod font;
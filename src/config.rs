@@ -0,0 +1,168 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+//
+// a small ini-style config file:
+//
+//     # comments start with a hash
+//     layout = fourths
+//
+//     [layout mine]
+//     notes = 48 49 50 51  44 45 46 47  40 41 42 43  36 37 38 39
+//
+// each section header is a kind ("layout") optionally followed by a name ("mine"). the
+// entries before the first header go in an unnamed section of kind "".
+//
+
+pub struct Entry {
+    pub key: String,
+    pub value: String,
+    pub line: usize
+}
+
+pub struct Section {
+    pub kind: String,
+    pub name: Option<String>,
+    pub line: usize,
+    pub entries: Vec<Entry>
+}
+
+impl Section {
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.key == key)
+    }
+}
+
+pub struct Config {
+    pub sections: Vec<Section>
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            sections: vec![Section {
+                kind: String::new(),
+                name: None,
+                line: 0,
+                entries: Vec::new()
+            }]
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+
+        for (idx, line) in text.lines().enumerate() {
+            let line_nr = idx + 1;
+
+            let line = match line.find('#') {
+                Some(comment) => &line[.. comment],
+                None => line
+            }.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return Err(format!("line {}: unterminated section header", line_nr));
+                }
+
+                let mut words = line[1 .. line.len() - 1].split_whitespace();
+
+                let kind = match words.next() {
+                    Some(kind) => kind.to_string(),
+                    None => return Err(format!("line {}: empty section header", line_nr))
+                };
+
+                let name = words.collect::<Vec<_>>().join(" ");
+
+                config.sections.push(Section {
+                    kind: kind,
+                    name: if name.is_empty() { None } else { Some(name) },
+                    line: line_nr,
+                    entries: Vec::new()
+                });
+
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(eq) => (line[.. eq].trim(), line[eq + 1 ..].trim()),
+                None => return Err(format!("line {}: expected \"key = value\"", line_nr))
+            };
+
+            if key.is_empty() {
+                return Err(format!("line {}: missing key", line_nr));
+            }
+
+            config.sections.last_mut().unwrap().entries.push(Entry {
+                key: key.to_string(),
+                value: value.to_string(),
+                line: line_nr
+            });
+        }
+
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let mut text = String::new();
+
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+
+        Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // the entries before the first section header.
+    pub fn global(&self) -> &Section {
+        &self.sections[0]
+    }
+
+    pub fn sections<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = &'a Section> + 'a {
+        self.sections.iter().skip(1).filter(move |section| section.kind == kind)
+    }
+}
+
+// values are written as they would be read out loud, e.g. "yes" or "off".
+pub fn parse_bool(entry: &Entry) -> Result<bool, String> {
+    match &*entry.value {
+        "yes" | "on" | "true" | "1" => Ok(true),
+        "no" | "off" | "false" | "0" => Ok(false),
+        _ => Err(format!("line {}: {} should be yes or no", entry.line, entry.key))
+    }
+}
+
+// an RGB color as six hex digits, optionally with a leading "0x" (a "#" would start a
+// comment).
+pub fn parse_color(entry: &Entry, value: &str) -> Result<u32, String> {
+    let digits = value.trim_start_matches("0x");
+
+    match u32::from_str_radix(digits, 16) {
+        Ok(color) if digits.len() == 6 => Ok(color),
+        _ => Err(format!("line {}: bad color \"{}\" for {}", entry.line, value, entry.key))
+    }
+}
@@ -15,8 +15,12 @@
 //  License along with this program.  If not, see
 //  <http://www.gnu.org/licenses/>.
 
-use std::mem::transmute;
-use std::os::unix::io;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::{
+    Duration,
+    Instant
+};
 
 extern crate nix;
 use nix::unistd;
@@ -27,9 +31,26 @@ use base::{
     MaschineButton,
 
     MaschinePad,
-    MaschinePadStateTransition
+    MaschinePadStateTransition,
+
+    CrosstalkFilter,
+    ButtonGestures
 };
 
+use base::font::render_text;
+
+fn io_error(err: nix::Error) -> io::Error {
+    io::Error::from_raw_os_error(err.errno() as i32)
+}
+
+// 128x64 pixels, in eight rows ("pages") of 8 pixels each. each byte is one column of a
+// page, top pixel in the lowest bit.
+const SCREEN_WIDTH: usize = 128;
+const SCREEN_PAGES: usize = 8;
+
+// the screen is written in four strips, each 32 columns wide and the full height.
+const SCREEN_STRIP_WIDTH: usize = 32;
+
 const BUTTON_REPORT_TO_MIKROBUTTONS_MAP: [[Option<MaschineButton>; 8]; 4] = [
     [
         Some(MaschineButton::Restart),
@@ -83,11 +104,16 @@ struct ButtonReport {
 }
 
 pub struct Mikro {
-    dev: io::RawFd,
+    dev: RawFd,
     light_buf: [u8; 79],
 
     pads: [MaschinePad; 16],
+    crosstalk: CrosstalkFilter,
     buttons: [u8; 5],
+    gestures: ButtonGestures,
+
+    screen: [u8; SCREEN_WIDTH * SCREEN_PAGES],
+    screen_dirty: bool,
 
     midi_note_base: u8
 }
@@ -114,13 +140,18 @@ impl Mikro {
         ]
     }
 
-    pub fn new(dev: io::RawFd) -> Self {
+    pub fn new(dev: RawFd) -> Self {
         let mut _self = Mikro {
             dev: dev,
             light_buf: [0u8; 79],
 
             pads: Mikro::sixteen_maschine_pads(),
+            crosstalk: CrosstalkFilter::default(),
             buttons: [0, 0, 0, 0, 0x10],
+            gestures: ButtonGestures::default(),
+
+            screen: [0u8; SCREEN_WIDTH * SCREEN_PAGES],
+            screen_dirty: false,
 
             midi_note_base: 48
         };
@@ -129,7 +160,7 @@ impl Mikro {
         return _self;
     }
 
-    fn read_buttons(&mut self, handler: &mut dyn MaschineHandler, buf: &[u8]) {
+    fn read_buttons(&mut self, handler: &mut dyn MaschineHandler, buf: &[u8], time: Instant) {
         for (idx, &byte) in buf[0..4].iter().enumerate() {
             let mut diff = (byte ^ self.buttons[idx]) as u32;
 
@@ -140,9 +171,14 @@ impl Mikro {
                     .expect("unknown button received from device");
 
                 if (byte & (1 << (off - 1))) != 0 {
-                    handler.button_down(self, btn);
+                    handler.button_down(self, btn, time);
+
+                    for gesture in self.gestures.button_down(btn, time) {
+                        gesture.dispatch(self, handler, time);
+                    }
                 } else {
-                    handler.button_up(self, btn);
+                    self.gestures.button_up(btn);
+                    handler.button_up(self, btn, time);
                 }
 
                 diff >>= off;
@@ -159,29 +195,79 @@ impl Mikro {
         }
 
         if ((self.buttons[4] + 1) & 0xF) == buf[4] {
-            handler.encoder_step(self, 0, 1);
+            handler.encoder_step(self, 0, 1, time);
         } else {
-            handler.encoder_step(self, 0, -1);
+            handler.encoder_step(self, 0, -1, time);
         }
 
         self.buttons[4] = buf[4];
     }
 
-    fn read_pads(&mut self, handler: &mut dyn MaschineHandler, buf: &[u8]) {
-        let pads: &[u16] = unsafe { transmute(buf) };
+    fn read_pads(&mut self, handler: &mut dyn MaschineHandler, buf: &[u8], time: Instant) {
+        if buf.len() < 16 * 2 {
+            println!(" :: short pad report ({} bytes)", buf.len());
+            return;
+        }
 
+        let mut raw = [0u16; 16];
+
+        // each pad is a little-endian word, with the pad's value in the low 12 bits. the
+        // report starts at an odd offset in the read buffer, so it's put together byte by
+        // byte rather than read as a [u16].
         for i in 0..16 {
-            let pressure = ((pads[i] & 0xFFF) as f32) / 4095.0;
+            raw[i] = ((buf[i * 2] as u16) | ((buf[i * 2 + 1] as u16) << 8)) & 0xFFF;
+        }
+
+        handler.pads_raw(self, &raw, time);
 
-            match self.pads[i].pressure_val(pressure) {
+        let mut pressures = [0.0f32; 16];
+        let mut transitions = [MaschinePadStateTransition::AtRest; 16];
+
+        // run every pad through its state machine before notifying the handler, so that the
+        // crosstalk filter can compare all of the onsets which landed in this report. the
+        // filter gets the same (median filtered) pressure that the state machine decided the
+        // onset on, not the raw one.
+        for i in 0..16 {
+            pressures[i] = (raw[i] as f32) / 4095.0;
+            transitions[i] = self.pads[i].pressure_val(pressures[i]);
+
+            match transitions[i] {
+                MaschinePadStateTransition::Pressed =>
+                    self.crosstalk.onset(i, self.pads[i].get_pressure(), time),
+
+                MaschinePadStateTransition::Aftertouch =>
+                    self.crosstalk.aftertouch(i, self.pads[i].get_pressure(), time),
+
+                _ => {}
+            }
+        }
+
+        for i in 0..16 {
+            let pressure = pressures[i];
+
+            match transitions[i] {
                 MaschinePadStateTransition::Pressed =>
-                    handler.pad_pressed(self, i, pressure),
+                    if !self.crosstalk.is_crosstalk(i, time) {
+                        handler.pad_pressed(self, i, pressure, time);
+
+                        for gesture in self.gestures.pad_pressed(i, pressure) {
+                            gesture.dispatch(self, handler, time);
+                        }
+                    },
 
                 MaschinePadStateTransition::Aftertouch =>
-                    handler.pad_aftertouch(self, i, pressure),
+                    if !self.crosstalk.is_suppressed(i) {
+                        handler.pad_aftertouch(self, i, pressure, time)
+                    },
 
-                MaschinePadStateTransition::Released =>
-                    handler.pad_released(self, i),
+                MaschinePadStateTransition::Released => {
+                    let suppressed = self.crosstalk.is_suppressed(i);
+                    self.crosstalk.released(i);
+
+                    if !suppressed {
+                        handler.pad_released(self, i, time);
+                    }
+                },
 
                 _ => {}
             }
@@ -198,12 +284,13 @@ fn set_rgb_light(rgb: &mut [u8], color: u32, brightness: f32) {
 }
 
 impl Maschine for Mikro {
-    fn get_fd(&self) -> io::RawFd {
+    fn get_fd(&self) -> RawFd {
         return self.dev;
     }
 
-    fn write_lights(&mut self) {
-        unistd::write(self.dev, &self.light_buf).unwrap();
+    fn write_lights(&mut self) -> io::Result<()> {
+        unistd::write(self.dev, &self.light_buf).map_err(io_error)?;
+        Ok(())
     }
 
     fn set_pad_light(&mut self, pad: usize, color: u32, brightness: f32) {
@@ -221,6 +308,22 @@ impl Maschine for Mikro {
       return self.midi_note_base;
     }
 
+    fn set_crosstalk_rejection(&mut self, ratio: f32, window: Duration) {
+        self.crosstalk.configure(ratio, window);
+    }
+
+    fn set_gesture_timings(&mut self, long_press: Duration, double_press: Duration) {
+        self.gestures.set_timings(long_press, double_press);
+    }
+
+    fn tick(&mut self, handler: &mut dyn MaschineHandler) {
+        let time = Instant::now();
+
+        for gesture in self.gestures.tick(time) {
+            gesture.dispatch(self, handler, time);
+        }
+    }
+
     fn set_button_light(&mut self, btn: MaschineButton, color: u32, brightness: f32) {
         let idx = match btn {
             MaschineButton::F1 => 1,
@@ -271,22 +374,28 @@ impl Maschine for Mikro {
         self.light_buf[idx] = (brightness * 255.0) as u8;
     }
 
-    fn readable(&mut self, handler: &mut dyn MaschineHandler) {
+    fn readable(&mut self, handler: &mut dyn MaschineHandler) -> io::Result<()> {
         let mut buf = [0u8; 256];
 
         let nbytes = match unistd::read(self.dev, &mut buf) {
-            Err(err) => panic!("read failed: {}", err.to_string()),
+            Err(err) => return Err(io_error(err)),
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "device went away")),
             Ok(nbytes) => nbytes
         };
 
+        let time = Instant::now();
+
         let report_nr = buf[0];
         let buf = &buf[1 .. nbytes];
 
         match report_nr {
-            0x01 => self.read_buttons(handler, &buf),
-            0x20 => self.read_pads(handler, &buf),
+            0x01 => self.read_buttons(handler, &buf, time),
+            0x20 => self.read_pads(handler, &buf, time),
             _ => println!(" :: {:2X}: got {} bytes", report_nr, nbytes)
         }
+
+        handler.report_processed(self, time);
+        Ok(())
     }
 
     fn get_pad_pressure(&self, pad_idx: usize) -> Result<f32, ()> {
@@ -296,17 +405,55 @@ impl Maschine for Mikro {
         }
     }
 
+    fn set_screen_text(&mut self, line: usize, text: &str) {
+        if line >= SCREEN_PAGES {
+            return;
+        }
+
+        let page = &mut self.screen[line * SCREEN_WIDTH .. (line + 1) * SCREEN_WIDTH];
+        render_text(text, page);
+
+        self.screen_dirty = true;
+    }
+
     fn clear_screen(&mut self) {
-        let mut screen_buf = [0u8; 1 + 8 + 256];
+        for byte in self.screen.iter_mut() {
+            *byte = 0;
+        }
+
+        // it goes out with the next write_screen().
+        self.screen_dirty = true;
+    }
+
+    // only does anything if the screen's changed since it was last written.
+    fn write_screen(&mut self) -> io::Result<()> {
+        if !self.screen_dirty {
+            return Ok(());
+        }
+
+        let mut screen_buf = [0u8; 1 + 8 + SCREEN_STRIP_WIDTH * SCREEN_PAGES];
 
         screen_buf[0] = 0xE0;
 
-        screen_buf[5] = 0x20;
-        screen_buf[7] = 0x08;
+        screen_buf[5] = SCREEN_STRIP_WIDTH as u8;
+        screen_buf[7] = SCREEN_PAGES as u8;
+
+        for strip in 0 .. SCREEN_WIDTH / SCREEN_STRIP_WIDTH {
+            let x = strip * SCREEN_STRIP_WIDTH;
+            screen_buf[1] = x as u8;
+
+            for page in 0 .. SCREEN_PAGES {
+                let src = page * SCREEN_WIDTH + x;
+                let dst = 9 + page * SCREEN_STRIP_WIDTH;
 
-        for i in 0..4 {
-            screen_buf[1] = i * 32;
-            unistd::write(self.dev, &screen_buf).unwrap();
+                screen_buf[dst .. dst + SCREEN_STRIP_WIDTH]
+                    .copy_from_slice(&self.screen[src .. src + SCREEN_STRIP_WIDTH]);
+            }
+
+            unistd::write(self.dev, &screen_buf).map_err(io_error)?;
         }
+
+        self.screen_dirty = false;
+        Ok(())
     }
 }
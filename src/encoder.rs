@@ -0,0 +1,98 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use midi::U7;
+
+use config::Entry;
+
+// how encoder movements are turned into CC values.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EncoderMode {
+    // the encoder turns a value between `min` and `max`, which is sent as it changes.
+    Absolute { min: U7, max: U7 },
+
+    // the rest send how far the encoder moved, as 1 for one step up and...
+    TwosComplement, // ...127 (0x7F) for one step down.
+    SignMagnitude,  // ...65 (0x41) for one step down.
+    BinaryOffset    // ...63 (0x3F) for one step down, and 65 for one step up.
+}
+
+pub fn parse_encoder_mode(entry: &Entry) -> Result<EncoderMode, String> {
+    match &*entry.value {
+        "absolute" => Ok(EncoderMode::Absolute { min: 0, max: 127 }),
+        "twos_complement" => Ok(EncoderMode::TwosComplement),
+        "sign_magnitude" => Ok(EncoderMode::SignMagnitude),
+        "binary_offset" => Ok(EncoderMode::BinaryOffset),
+        _ => Err(format!("line {}: unknown encoder mode {}", entry.line, entry.value))
+    }
+}
+
+// the largest move a relative encoding can send in one message.
+const MAX_RELATIVE_STEP: i32 = 63;
+
+#[derive(Clone, Debug)]
+pub struct EncoderCc {
+    pub controller: U7,
+    pub mode: EncoderMode,
+
+    // where the encoder is at, in absolute mode.
+    value: U7
+}
+
+impl EncoderCc {
+    // in absolute mode, the value starts out at the bottom of the range.
+    pub fn new(controller: U7, mode: EncoderMode) -> Self {
+        EncoderCc {
+            controller: controller,
+            mode: mode,
+            value: match mode {
+                EncoderMode::Absolute { min, .. } => min,
+                _ => 0
+            }
+        }
+    }
+
+    // the CC value to send for the encoder moving `delta` steps, if there's anything to
+    // send (turning past either end of the range in absolute mode doesn't change anything).
+    pub fn step(&mut self, delta: i32) -> Option<U7> {
+        if delta == 0 {
+            return None;
+        }
+
+        let relative = delta.clamp(-MAX_RELATIVE_STEP, MAX_RELATIVE_STEP);
+
+        match self.mode {
+            EncoderMode::Absolute { min, max } => {
+                let value = (self.value as i32 + delta).clamp(min as i32, max as i32) as U7;
+
+                if value == self.value {
+                    return None;
+                }
+
+                self.value = value;
+                Some(value)
+            },
+
+            EncoderMode::TwosComplement => Some((relative & 0x7F) as U7),
+
+            EncoderMode::SignMagnitude =>
+                Some(if relative < 0 { 0x40 | (-relative as U7) } else { relative as U7 }),
+
+            EncoderMode::BinaryOffset => Some((64 + relative) as U7)
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+// pad light feedback: notes sent to a "Feedback MIDI" port light up the pads which would
+// play them, so that a sequencer or DAW can show what it's playing back. only there with
+// the ALSA backend.
+
+use libc::c_int;
+
+use midi::*;
+use alsa_seq::*;
+
+use base::Maschine;
+
+use {
+    Controller,
+    MHandler
+};
+
+pub fn create_feedback_port(seq_handle: &SequencerHandle, name: &str) -> SequencerPort {
+    seq_handle.create_port(
+        name, PortCapabilities::PORT_CAPABILITY_WRITE | PortCapabilities::PORT_CAPABILITY_SUBS_WRITE, PortType::MidiGeneric)
+            .unwrap()
+}
+
+// hands a message which arrived on port `port_nr` of our client to the controller whose
+// feedback port that is.
+pub fn route_feedback(controllers: &mut [Controller], port_nr: c_int, msg: &Message) {
+    for ctl in controllers.iter_mut() {
+        if ctl.handler.feedback_port.as_ref().map(|port| port.number()) == Some(port_nr) {
+            ctl.handler.midi_feedback(&mut *ctl.dev, msg);
+        }
+    }
+}
+
+impl<'a> MHandler<'a> {
+    pub fn midi_feedback(&self, maschine: &mut dyn Maschine, msg: &Message) {
+        let (channel, note, brightness) = match *msg {
+            Message::NoteOn(ch, note, 0) | Message::NoteOff(ch, note, _) => (ch, note, None),
+
+            Message::NoteOn(ch, note, velocity) =>
+                (ch, note, Some(((velocity as f32) / 127.0).sqrt())),
+
+            _ => return
+        };
+
+        // a pad only shows the notes on its own channel.
+        for pad_idx in 0 .. 16 {
+            if self.pad_channel(pad_idx) != channel
+                || self.pad_note(maschine, pad_idx) != Some(note) {
+                continue;
+            }
+
+            match brightness {
+                Some(brightness) => maschine.set_pad_light(pad_idx, self.pad_color(), brightness),
+                None => self.light_idle_pad(maschine, pad_idx)
+            }
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use midi::U7;
+
+use config::{
+    Config,
+    Section,
+    parse_bool,
+    parse_color
+};
+
+// how a pad is lit while it isn't being played.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PadColor {
+    // the handler's own color.
+    Default,
+    Rgb(u32),
+    Off
+}
+
+// which note each pad plays. pads are numbered as the device numbers them: left to right,
+// top row first.
+#[derive(Clone, Debug)]
+pub struct Layout {
+    pub name: String,
+
+    // relative layouts are offsets from the device's note base, the others are the notes
+    // themselves.
+    notes: [U7; 16],
+    relative: bool,
+
+    colors: [PadColor; 16],
+
+    // pads which play a black key stay dark, whatever the note base.
+    dark_black_keys: bool
+}
+
+const CHROMATIC_NOTES: [U7; 16] = [
+    12, 13, 14, 15,
+     8,  9, 10, 11,
+     4,  5,  6,  7,
+     0,  1,  2,  3
+];
+
+// rows a fourth apart, like the strings of a bass.
+const FOURTHS_NOTES: [U7; 16] = [
+    15, 16, 17, 18,
+    10, 11, 12, 13,
+     5,  6,  7,  8,
+     0,  1,  2,  3
+];
+
+// cymbals on top, then percussion, toms, and kick, snare and hats on the bottom row.
+const GM_DRUM_NOTES: [U7; 16] = [
+    49, 57, 51, 53,
+    37, 39, 54, 56,
+    41, 45, 48, 50,
+    36, 38, 42, 46
+];
+
+const KICK: PadColor = PadColor::Rgb(0xFF0000);
+const SNARE: PadColor = PadColor::Rgb(0xFF6000);
+const HAT: PadColor = PadColor::Rgb(0xFFD000);
+const TOM: PadColor = PadColor::Rgb(0x00FF40);
+const PERCUSSION: PadColor = PadColor::Rgb(0x00C0FF);
+const CYMBAL: PadColor = PadColor::Rgb(0xA000FF);
+
+const GM_DRUM_COLORS: [PadColor; 16] = [
+    CYMBAL, CYMBAL, CYMBAL, CYMBAL,
+    SNARE, SNARE, PERCUSSION, PERCUSSION,
+    TOM, TOM, TOM, TOM,
+    KICK, SNARE, HAT, HAT
+];
+
+pub const ROOT_COLOR: PadColor = PadColor::Rgb(0x0040FF);
+
+// pads counted the way they're labelled on the hardware: from the bottom left, left to
+// right and then up a row.
+pub fn pad_number(pad_idx: usize) -> usize {
+    (3 - pad_idx / 4) * 4 + pad_idx % 4
+}
+
+fn is_black_key(note: U7) -> bool {
+    match note % 12 {
+        1 | 3 | 6 | 8 | 10 => true,
+        _ => false
+    }
+}
+
+impl Layout {
+    // the pads as they've always been: four semitones to a row, bottom left up. the black
+    // keys stay dark.
+    pub fn chromatic() -> Self {
+        Layout {
+            name: "chromatic".to_string(),
+            notes: CHROMATIC_NOTES,
+            relative: true,
+            colors: [PadColor::Default; 16],
+            dark_black_keys: true
+        }
+    }
+
+    // the note base's own pitch class is lit up, so there's something to find your way by.
+    pub fn fourths() -> Self {
+        let mut colors = [PadColor::Default; 16];
+
+        for (color, &offset) in colors.iter_mut().zip(FOURTHS_NOTES.iter()) {
+            if offset % 12 == 0 {
+                *color = ROOT_COLOR;
+            }
+        }
+
+        Layout {
+            name: "fourths".to_string(),
+            notes: FOURTHS_NOTES,
+            relative: true,
+            colors: colors,
+            dark_black_keys: false
+        }
+    }
+
+    pub fn gm_drums() -> Self {
+        Layout {
+            name: "gm_drums".to_string(),
+            notes: GM_DRUM_NOTES,
+            relative: false,
+            colors: GM_DRUM_COLORS,
+            dark_black_keys: false
+        }
+    }
+
+    pub fn builtin() -> Vec<Layout> {
+        vec![Layout::chromatic(), Layout::gm_drums(), Layout::fourths()]
+    }
+
+    // the note `pad_idx` plays, if it plays one at all (a relative layout can run off the
+    // top of the note range).
+    pub fn note(&self, note_base: U7, pad_idx: usize) -> Option<U7> {
+        let note = if self.relative {
+            note_base as u32 + self.notes[pad_idx] as u32
+        } else {
+            self.notes[pad_idx] as u32
+        };
+
+        if note <= 127 { Some(note as U7) } else { None }
+    }
+
+    // which keys are black depends on where the note base puts the pads, so it's worked out
+    // here rather than when the layout's made.
+    pub fn pad_color(&self, note_base: U7, pad_idx: usize) -> PadColor {
+        match self.note(note_base, pad_idx) {
+            Some(note) if self.dark_black_keys && is_black_key(note) => PadColor::Off,
+            _ => self.colors[pad_idx]
+        }
+    }
+}
+
+// a [layout <name>] section:
+//
+//     notes = <16 notes, left to right, top row first>
+//     relative = <yes if the notes are offsets from the note base, defaults to no>
+//     colors = <16 colors as rrggbb, "default" or "off", defaults to all "default">
+fn layout_from_section(name: &str, section: &Section) -> Result<Layout, String> {
+    let notes_entry = match section.get("notes") {
+        Some(entry) => entry,
+        None => return Err(format!("line {}: layout {} has no notes", section.line, name))
+    };
+
+    let mut notes = [0; 16];
+    let values: Vec<_> = notes_entry.value.split_whitespace().collect();
+
+    if values.len() != 16 {
+        return Err(format!("line {}: a layout needs 16 notes, not {}", notes_entry.line,
+                           values.len()));
+    }
+
+    for (note, value) in notes.iter_mut().zip(values.iter()) {
+        *note = match value.parse::<U7>() {
+            Ok(n) if n <= 127 => n,
+            _ => return Err(format!("line {}: bad note \"{}\"", notes_entry.line, value))
+        };
+    }
+
+    let relative = match section.get("relative") {
+        Some(entry) => parse_bool(entry)?,
+        None => false
+    };
+
+    let mut colors = [PadColor::Default; 16];
+
+    if let Some(entry) = section.get("colors") {
+        let values: Vec<_> = entry.value.split_whitespace().collect();
+
+        if values.len() != 16 {
+            return Err(format!("line {}: a layout needs 16 colors, not {}", entry.line,
+                               values.len()));
+        }
+
+        for (color, value) in colors.iter_mut().zip(values.iter()) {
+            *color = match *value {
+                "default" => PadColor::Default,
+                "off" => PadColor::Off,
+                _ => PadColor::Rgb(parse_color(entry, value)?)
+            };
+        }
+    }
+
+    Ok(Layout {
+        name: name.to_string(),
+        notes: notes,
+        relative: relative,
+        colors: colors,
+        dark_black_keys: false
+    })
+}
+
+// the built-in layouts followed by the ones from the config file, in the order they're
+// cycled through, and the index of the one to start with (from a top-level "layout = ...").
+pub fn layouts_from_config(config: &Config) -> Result<(Vec<Layout>, usize), String> {
+    let mut layouts = Layout::builtin();
+
+    for section in config.sections("layout") {
+        let name = match section.name {
+            Some(ref name) => name,
+            None => return Err(format!("line {}: layout without a name", section.line))
+        };
+
+        let layout = layout_from_section(name, section)?;
+
+        // a layout with a built-in's name replaces it.
+        match layouts.iter().position(|l| l.name == layout.name) {
+            Some(idx) => layouts[idx] = layout,
+            None => layouts.push(layout)
+        }
+    }
+
+    let active = match config.global().get("layout") {
+        Some(entry) => match layouts.iter().position(|l| l.name == entry.value) {
+            Some(idx) => idx,
+            None => return Err(format!("line {}: no layout called {}", entry.line, entry.value))
+        },
+
+        None => 0
+    };
+
+    Ok((layouts, active))
+}
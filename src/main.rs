@@ -16,40 +16,101 @@
 //  <http://www.gnu.org/licenses/>.
 
 use std::path::Path;
-use std::os::unix::io::AsRawFd;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::fs::OpenOptions;
 use std::env;
 
 use std::net::{
     UdpSocket,
     SocketAddr,
+    ToSocketAddrs,
     SocketAddrV4,
     Ipv4Addr
 };
 
 use std::time::{
     Duration,
-    SystemTime
+    Instant
 };
 
 extern crate nix;
 use nix::fcntl::{O_RDWR, O_NONBLOCK};
 use nix::{fcntl,sys};
-use nix::poll::*;
+
+extern crate libc;
 
 extern crate midi;
 extern crate alsa_seq;
 use midi::*;
 use alsa_seq::*;
 
-extern crate hsl;
-use hsl::HSL;
-
 #[macro_use(osc_args)]
 extern crate tinyosc;
 use tinyosc as osc;
 
+#[cfg(feature = "jack")]
+extern crate jack;
+
 mod devices;
 mod base;
+mod reactor;
+mod sink;
+mod config;
+mod layout;
+mod scale;
+mod bank;
+mod encoder;
+mod feedback;
+
+#[cfg(test)]
+mod test;
+
+use reactor::Reactor;
+use config::Config;
+
+use feedback::{
+    create_feedback_port,
+    route_feedback
+};
+
+use layout::{
+    Layout,
+    PadColor,
+    ROOT_COLOR,
+    layouts_from_config
+};
+
+use bank::{
+    Bank,
+    BANK_NAMES,
+    banks_from_config,
+    bank_by_name,
+    global_channel_from_config
+};
+
+use layout::pad_number;
+
+use scale::{
+    SCALES,
+    NOTE_NAMES,
+    key_from_config,
+    parse_root,
+    scale_by_name
+};
+
+use sink::{
+    MidiSink,
+    AlsaSink,
+    RawMidiSink,
+    RtpMidiSink,
+    UmpSink,
+    NullSink,
+    channel_from_number
+};
+
+#[cfg(feature = "jack")]
+use sink::JackSink;
 
 use base::{
     Maschine,
@@ -57,35 +118,276 @@ use base::{
     MaschineButton
 };
 
-fn ev_loop(dev: &mut dyn Maschine, mhandler: &mut MHandler) {
-    let mut fds = [
-        PollFd::new(dev.get_fd(), POLLIN, EventFlags::empty()),
-        PollFd::new(mhandler.osc_socket.as_raw_fd(), POLLIN, EventFlags::empty())
-    ];
+use base::gesture::MODIFIERS;
 
-    let mut now = SystemTime::now();
-    let timer_interval = Duration::from_millis(16);
+// a device along with the handler which turns its input into MIDI and OSC.
+struct Controller<'a> {
+    dev: Box<dyn Maschine>,
+    handler: MHandler<'a>
+}
 
-    loop {
-        poll(&mut fds, 16).unwrap();
+// a controller whose device fails is dropped, taking its OSC prefix and MIDI port with it,
+// while the rest carry on.
+fn drop_controller(controllers: &mut Vec<Controller>, idx: usize, err: &io::Error) {
+    let ctl = controllers.remove(idx);
+    println!(" :: lost the device at {}: {}", ctl.handler.osc_prefix, err);
+}
 
-        if fds[0].revents().unwrap().contains(POLLIN) {
-            dev.readable(mhandler);
-        }
+// controllers are looked up by fd rather than by index, since dropping one shifts the others.
+fn controller_readable(controllers: &mut Vec<Controller>, fd: RawFd) -> io::Result<()> {
+    let idx = match controllers.iter().position(|ctl| ctl.dev.get_fd() == fd) {
+        Some(idx) => idx,
+        None => return Err(io::Error::new(io::ErrorKind::NotFound, "no controller for this fd"))
+    };
+
+    let result = {
+        let ctl = &mut controllers[idx];
+        ctl.dev.readable(&mut ctl.handler)
+    };
 
-        if fds[1].revents().unwrap().contains(POLLIN) {
-            mhandler.recv_osc_msg(dev);
+    if let Err(ref e) = result {
+        drop_controller(controllers, idx, e);
+    }
+
+    result
+}
+
+fn tick_controllers(controllers: &mut Vec<Controller>) {
+    let mut idx = 0;
+
+    while idx < controllers.len() {
+        let result = {
+            let ctl = &mut controllers[idx];
+            ctl.dev.tick(&mut ctl.handler);
+            ctl.dev.write_lights().and_then(|_| ctl.dev.write_screen())
+        };
+
+        match result {
+            Ok(_) => idx += 1,
+            Err(e) => drop_controller(controllers, idx, &e)
         }
+    }
+}
 
-        if now.elapsed().unwrap() >= timer_interval {
-            dev.write_lights();
-            now = SystemTime::now();
+fn ev_loop(controllers: &mut Vec<Controller>, osc_socket: &UdpSocket,
+           seq_handle: Option<&SequencerHandle>, connect: &[String]) {
+    let mut reactor = Reactor::new();
+
+    for ctl in controllers.iter() {
+        let fd = ctl.dev.get_fd();
+
+        reactor.add_fd(fd, move |controllers: &mut Vec<Controller>| {
+            controller_readable(controllers, fd)
+        });
+    }
+
+    reactor.add_fd(osc_socket.as_raw_fd(), |controllers: &mut Vec<Controller>| {
+        recv_osc_msg(osc_socket, controllers);
+        Ok(())
+    });
+
+    if let Some(seq_handle) = seq_handle {
+        for seq_fd in seq_handle.poll_descriptors() {
+            reactor.add_fd(seq_fd.fd, move |controllers: &mut Vec<Controller>| {
+                recv_seq_events(seq_handle, connect, controllers);
+                Ok(())
+            });
         }
     }
+
+    reactor.add_timer(Duration::from_millis(16), |controllers: &mut Vec<Controller>| {
+        tick_controllers(controllers);
+        Ok(())
+    }).unwrap();
+
+    if let Err(e) = reactor.run(controllers) {
+        panic!("event loop failed: {}", e);
+    }
 }
 
 fn usage(prog_name: &String) {
-    println!("usage: {} <hidraw device>", prog_name);
+    println!("usage: {} [options] <hidraw device> [<hidraw device> ...]", prog_name);
+    println!("");
+    println!("options:");
+    println!("    -c, --connect <client:port>   connect the pads' MIDI output to this ALSA port");
+    println!("                                  on startup. may be given more than once.");
+    println!("                                  the port can also be given by its name, or as");
+    println!("                                  \"<client name>:<port name>\".");
+    println!("    -f, --config <path>           read pad layouts, banks, the scale and such");
+    println!("                                  from this file.");
+    println!("    -l, --list-ports              list the ALSA ports that can be connected to");
+    println!("                                  and exit.");
+    println!("    -m, --midi <backend>          where the pads' MIDI goes. one of:");
+    println!("                                    alsa         an ALSA sequencer port (default)");
+    println!("                                    raw:<path>   a raw MIDI device, e.g.");
+    println!("                                                 /dev/snd/midiC1D0");
+    println!("                                    ump:<path>   a MIDI 2.0 UMP device, e.g.");
+    println!("                                                 /dev/snd/umpC1D0, with full");
+    println!("                                                 resolution velocity and pressure");
+    println!("                                    rtp:<host:port>");
+    println!("                                                 an RTP-MIDI session with the");
+    println!("                                                 peer listening on this port");
+    println!("                                    jack         a JACK MIDI port (if built with");
+    println!("                                                 the \"jack\" feature)");
+    println!("                                    null         nowhere");
+}
+
+#[derive(PartialEq)]
+enum MidiBackend {
+    Alsa,
+    RawMidi(String),
+    Ump(String),
+    RtpMidi(SocketAddr),
+
+    #[cfg(feature = "jack")]
+    Jack,
+
+    Null
+}
+
+fn parse_midi_backend(backend: &str) -> Result<MidiBackend, String> {
+    match backend {
+        "alsa" => Ok(MidiBackend::Alsa),
+        "null" => Ok(MidiBackend::Null),
+
+        #[cfg(feature = "jack")]
+        "jack" => Ok(MidiBackend::Jack),
+
+        _ if backend.starts_with("raw:") => Ok(MidiBackend::RawMidi(backend[4 ..].to_string())),
+
+        _ if backend.starts_with("ump:") => Ok(MidiBackend::Ump(backend[4 ..].to_string())),
+
+        _ if backend.starts_with("rtp:") => match backend[4 ..].to_socket_addrs() {
+            Ok(mut addrs) => match addrs.next() {
+                Some(addr) => Ok(MidiBackend::RtpMidi(addr)),
+                None => Err(format!("{} doesn't resolve to any address", &backend[4 ..]))
+            },
+
+            Err(e) => Err(format!("bad RTP-MIDI peer {}: {}", &backend[4 ..], e))
+        },
+
+        _ => Err(format!("unknown MIDI backend {}", backend))
+    }
+}
+
+struct Options {
+    dev_paths: Vec<String>,
+
+    // ALSA "client:port" addresses (or client names) to connect the pad output ports to.
+    connect: Vec<String>,
+
+    config: Option<String>,
+    list_ports: bool,
+    midi: MidiBackend
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut opts = Options {
+        dev_paths: Vec::new(),
+        connect: Vec::new(),
+        config: None,
+        list_ports: false,
+        midi: MidiBackend::Alsa
+    };
+
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match &**arg {
+            "-c" | "--connect" => match args.next() {
+                Some(address) => opts.connect.push(address.clone()),
+                None => return Err(format!("{} needs an argument", arg))
+            },
+
+            "-f" | "--config" => match args.next() {
+                Some(path) => opts.config = Some(path.clone()),
+                None => return Err(format!("{} needs an argument", arg))
+            },
+
+            "-l" | "--list-ports" => opts.list_ports = true,
+
+            "-m" | "--midi" => match args.next() {
+                Some(backend) => opts.midi = parse_midi_backend(backend)?,
+                None => return Err(format!("{} needs an argument", arg))
+            },
+
+            _ if arg.starts_with("-") => return Err(format!("unknown option {}", arg)),
+
+            _ => opts.dev_paths.push(arg.clone())
+        }
+    }
+
+    if opts.dev_paths.is_empty() && !opts.list_ports {
+        return Err("missing hidraw device path".to_string());
+    }
+
+    if !opts.connect.is_empty() && opts.midi != MidiBackend::Alsa {
+        return Err("--connect only works with the alsa MIDI backend".to_string());
+    }
+
+    Ok(opts)
+}
+
+// every port of every other client that we could send our pad MIDI to.
+fn connectable_ports(seq_handle: &SequencerHandle) -> Vec<(ClientInfo, PortInfo)> {
+    let mut found = Vec::new();
+
+    for client in seq_handle.clients().unwrap() {
+        for port in seq_handle.ports(client.number).unwrap() {
+            if port.is_writable() && !port.capabilities.contains(PortCapabilities::PORT_CAPABILITY_NO_EXPORT) {
+                found.push((client.clone(), port));
+            }
+        }
+    }
+
+    found
+}
+
+fn list_ports(seq_handle: &SequencerHandle) {
+    for (client, port) in connectable_ports(seq_handle) {
+        println!("{:>3}:{:<3}  {}:{}  {:?}",
+            port.address.client, port.address.port, client.name, port.name, port.types());
+    }
+}
+
+// anything ALSA can parse itself ("20:0", "20", "Client Name") is used as-is. failing that,
+// the target is looked up by port name, either on its own or as "<client name>:<port name>".
+fn resolve_target(seq_handle: &SequencerHandle, target: &str) -> Option<Address> {
+    if let Ok(address) = seq_handle.parse_address(target) {
+        return Some(address);
+    }
+
+    connectable_ports(seq_handle).into_iter()
+        .find(|&(ref client, ref port)|
+            port.name == target || format!("{}:{}", client.name, port.name) == target)
+        .map(|(_, port)| port.address)
+}
+
+fn auto_connect(seq_handle: &SequencerHandle, port: &SequencerPort, targets: &[String]) {
+    for target in targets.iter() {
+        let address = match resolve_target(seq_handle, target) {
+            Some(address) => address,
+            None => {
+                println!(" :: couldn't connect to {}: no such port", target);
+                continue;
+            }
+        };
+
+        if let Err(e) = port.connect_to(address) {
+            println!(" :: couldn't connect to {}: {}", target, e);
+        }
+    }
+}
+
+// the port the pads send from, connected to the --connect targets.
+fn create_pads_port(seq_handle: &SequencerHandle, name: &str, connect: &[String])
+    -> SequencerPort {
+    let pads_port = seq_handle.create_port(
+        name, PortCapabilities::PORT_CAPABILITY_READ | PortCapabilities::PORT_CAPABILITY_SUBS_READ, PortType::MidiGeneric)
+            .unwrap();
+
+    auto_connect(seq_handle, &pads_port, connect);
+    pads_port
 }
 
 const PAD_RELEASED_BRIGHTNESS: f32 = 0.015;
@@ -98,13 +400,47 @@ enum PressureShape {
 }
 
 struct MHandler<'a> {
-    color: HSL,
+    midi: Box<dyn MidiSink>,
 
-    seq_handle: &'a SequencerHandle,
-    seq_port: &'a SequencerPort<'a>,
+    // see feedback.rs.
+    feedback_port: Option<SequencerPort>,
 
     pressure_shape: PressureShape,
     send_aftertouch: bool,
+    send_raw_pads: bool,
+
+    // whether input event messages carry the time they happened at (see
+    // send_osc_event_msg()).
+    send_timestamps: bool,
+
+    layouts: Vec<Layout>,
+    layout_idx: usize,
+
+    // the channel for any pad which its bank doesn't give one.
+    channel: Channel,
+
+    // each bank has its own note base, channel and color. the device's note base is kept
+    // in step with the active bank's.
+    banks: Vec<Bank>,
+    bank_idx: usize,
+
+    // with a scale, the pads play its notes from `root` (0 for C up to 11 for B) instead
+    // of the layout's.
+    scale_idx: Option<usize>,
+    root: u8,
+
+    // so that the encoder can do something else while one of these is held.
+    held_buttons: Vec<MaschineButton>,
+
+    // the note each pad is playing and the channel it's on, so that changing the layout
+    // (or bank) under a held pad doesn't leave its note hanging.
+    held_notes: [Option<(Channel, U7)>; 16],
+
+    // "/maschine" when running a single device, "/maschine/<n>" when running several.
+    osc_prefix: String,
+
+    // OSC timestamps are sent relative to this, since `Instant` has no meaningful absolute value.
+    epoch: Instant,
 
     osc_socket: &'a UdpSocket,
     osc_outgoing_addr: SocketAddr
@@ -189,59 +525,153 @@ fn btn_to_osc_button_map(btn: MaschineButton) -> &'static str {
 }
 
 impl<'a> MHandler<'a> {
+    fn bank(&self) -> &Bank {
+        &self.banks[self.bank_idx]
+    }
+
     fn pad_color(&self) -> u32 {
-        let (r, g, b) = self.color.to_rgb();
+        self.bank().color
+    }
 
-          ((r as u32) << 16)
-        | ((g as u32) << 8)
-        |  (b as u32)
+    fn pad_channel(&self, pad_idx: usize) -> Channel {
+        self.bank().pad_channel(pad_idx, self.channel)
     }
 
-    fn pressure_to_vel(&self, pressure: f32) -> U7 {
-        (match self.pressure_shape {
+    fn shape_pressure(&self, pressure: f32) -> f32 {
+        match self.pressure_shape {
             PressureShape::Linear => pressure,
             PressureShape::Exponential(power) => pressure.powf(power),
             PressureShape::Constant(c_pressure) => c_pressure
-        } * 127.0) as U7
+        }
     }
 
-    #[allow(dead_code)]
-    fn update_pad_colors(&self, maschine: &mut dyn Maschine) {
-        for i in 0..16 {
-            let brightness = match maschine.get_pad_pressure(i).unwrap() {
-                b if b == 0.0 => PAD_RELEASED_BRIGHTNESS,
-                pressure @ _ => pressure.sqrt()
-            };
+    fn pressure_to_vel(&self, pressure: f32) -> U7 {
+        (self.shape_pressure(pressure) * 127.0) as U7
+    }
 
-            maschine.set_pad_light(i, self.pad_color(), brightness);
+    fn layout(&self) -> &Layout {
+        &self.layouts[self.layout_idx]
+    }
+
+    fn pad_note(&self, maschine: &dyn Maschine, pad_idx: usize) -> Option<U7> {
+        let note_base = maschine.get_midi_note_base();
+
+        match self.scale_idx {
+            Some(scale_idx) => SCALES[scale_idx].note(self.root, note_base, pad_idx),
+            None => self.layout().note(note_base, pad_idx)
         }
     }
 
-    fn recv_osc_msg(&self, maschine: &mut dyn Maschine) {
-        let mut buf = [0u8; 128];
+    // how a pad looks when nobody's playing it, which depends on the layout (or the scale).
+    fn light_idle_pad(&self, maschine: &mut dyn Maschine, pad_idx: usize) {
+        let color = match self.scale_idx {
+            Some(scale_idx) if SCALES[scale_idx].is_root(pad_idx) => ROOT_COLOR,
+            Some(_) => PadColor::Default,
+            None => self.layout().pad_color(maschine.get_midi_note_base(), pad_idx)
+        };
 
-        let nbytes = match self.osc_socket.recv_from(&mut buf) {
-            Ok((nbytes, _)) => nbytes,
-            Err(e) => {
-                println!(" :: error in recv_from(): {}", e);
-                return;
+        match color {
+            PadColor::Default =>
+                maschine.set_pad_light(pad_idx, self.pad_color(), PAD_RELEASED_BRIGHTNESS),
+            PadColor::Rgb(color) =>
+                maschine.set_pad_light(pad_idx, color, PAD_RELEASED_BRIGHTNESS),
+            PadColor::Off => maschine.set_pad_light(pad_idx, 0, 0.0)
+        }
+    }
+
+    fn light_idle_pads(&self, maschine: &mut dyn Maschine) {
+        for pad_idx in 0 .. 16 {
+            if self.held_notes[pad_idx].is_none() {
+                self.light_idle_pad(maschine, pad_idx);
             }
+        }
+    }
+
+    fn set_layout(&mut self, maschine: &mut dyn Maschine, layout_idx: usize) {
+        self.layout_idx = layout_idx % self.layouts.len();
+        self.light_idle_pads(maschine);
+        self.update_screen(maschine);
+
+        let name = self.layout().name.clone();
+        self.send_osc_msg(&*format!("{}/layout", self.osc_prefix), osc_args![&*name]);
+    }
+
+    fn select_bank(&mut self, maschine: &mut dyn Maschine, bank_idx: usize) {
+        self.bank_idx = bank_idx % self.banks.len();
+
+        maschine.set_midi_note_base(self.bank().note_base);
+        maschine.set_button_light(MaschineButton::Group, self.bank().color, 1.0);
+
+        self.light_idle_pads(maschine);
+        self.update_screen(maschine);
+
+        let name = BANK_NAMES[self.bank_idx];
+        self.send_osc_msg(&*format!("{}/bank", self.osc_prefix), osc_args![name]);
+    }
+
+    fn set_key(&mut self, maschine: &mut dyn Maschine, scale_idx: Option<usize>, root: u8) {
+        self.scale_idx = scale_idx;
+        self.root = root % 12;
+
+        self.light_idle_pads(maschine);
+        self.update_screen(maschine);
+    }
+
+    // steps through the scales (and back to no scale at all, at either end).
+    fn step_scale(&mut self, maschine: &mut dyn Maschine, delta: i32) {
+        let choices = SCALES.len() as i32 + 1;
+        let current = self.scale_idx.map_or(0, |idx| idx as i32 + 1);
+
+        let next = match (current + delta).rem_euclid(choices) {
+            0 => None,
+            n => Some((n - 1) as usize)
         };
 
-        let msg = match osc::Message::deserialize(&buf[.. nbytes]) {
-            Ok(msg) => msg,
-            Err(_) => {
-                println!(" :: couldn't decode OSC message :c");
-                return;
+        let root = self.root;
+        self.set_key(maschine, next, root);
+    }
+
+    fn step_root(&mut self, maschine: &mut dyn Maschine, delta: i32) {
+        let root = (self.root as i32 + delta).rem_euclid(12) as u8;
+        let scale_idx = self.scale_idx;
+
+        self.set_key(maschine, scale_idx, root);
+    }
+
+    fn update_screen(&self, maschine: &mut dyn Maschine) {
+        maschine.set_screen_text(2, &*format!("bank: {}", BANK_NAMES[self.bank_idx]));
+
+        match self.scale_idx {
+            Some(scale_idx) => {
+                maschine.set_screen_text(0, &*format!("scale: {}", SCALES[scale_idx].name));
+                maschine.set_screen_text(1, &*format!("root: {}",
+                                                       NOTE_NAMES[self.root as usize]));
+            },
+
+            None => {
+                maschine.set_screen_text(0, &*format!("layout: {}", self.layout().name));
+                maschine.set_screen_text(1, "");
             }
-        };
+        }
+    }
+
+    #[allow(dead_code)]
+    fn update_pad_colors(&self, maschine: &mut dyn Maschine) {
+        for i in 0..16 {
+            let brightness = match maschine.get_pad_pressure(i).unwrap() {
+                b if b == 0.0 => PAD_RELEASED_BRIGHTNESS,
+                pressure @ _ => pressure.sqrt()
+            };
 
-        self.handle_osc_messge(maschine, &msg);
+            maschine.set_pad_light(i, self.pad_color(), brightness);
+        }
     }
 
-    fn handle_osc_messge(&self, maschine: &mut dyn Maschine, msg: &osc::Message) {
-        if msg.path.starts_with("/maschine/button") {
-            let btn = match osc_button_to_btn_map(&msg.path[17 ..]) {
+    // `path` is the message's path with this handler's prefix stripped off.
+    fn handle_osc_messge(&mut self, maschine: &mut dyn Maschine, path: &str,
+                         msg: &osc::Message) {
+        if path.starts_with("/button/") {
+            let btn = match osc_button_to_btn_map(&path[8 ..]) {
                 Some(btn) => btn,
                 None => return
             };
@@ -264,7 +694,7 @@ impl<'a> MHandler<'a> {
                 _ => return
             };
         }
-        else if msg.path.starts_with("/maschine/pad") {
+        else if path.starts_with("/pad") {
             match msg.arguments.len() {
                 3 => {
                     if let (&osc::Argument::i(pad), &osc::Argument::i(color), &osc::Argument::f(brightness))
@@ -276,16 +706,188 @@ impl<'a> MHandler<'a> {
                 _ => return
             }
         }
-        else if msg.path.starts_with("/maschine/midi_note_base") {
+        else if path.starts_with("/midi_note_base") {
             match msg.arguments.len() {
                 1 => {
                   if let osc::Argument::i(base) = msg.arguments[0] {
                     maschine.set_midi_note_base(base as u8);
+                    self.banks[self.bank_idx].note_base = base as u8;
+                    self.light_idle_pads(maschine);
                   }
                 }
                 _ => return
             }
         }
+        else if path.starts_with("/crosstalk_rejection") {
+            match msg.arguments.len() {
+                2 => {
+                    if let (&osc::Argument::f(ratio), &osc::Argument::i(window_ms))
+                        = (&msg.arguments[0], &msg.arguments[1]) {
+                        maschine.set_crosstalk_rejection(ratio,
+                            Duration::from_millis(window_ms.max(0) as u64));
+                    }
+                }
+                _ => return
+            }
+        }
+        else if path.starts_with("/gesture_timing") {
+            match msg.arguments.len() {
+                2 => {
+                    if let (&osc::Argument::i(long_ms), &osc::Argument::i(double_ms))
+                        = (&msg.arguments[0], &msg.arguments[1]) {
+                        maschine.set_gesture_timings(
+                            Duration::from_millis(long_ms.max(0) as u64),
+                            Duration::from_millis(double_ms.max(0) as u64));
+                    }
+                }
+                _ => return
+            }
+        }
+        else if path.starts_with("/layout") {
+            match msg.arguments.len() {
+                1 => {
+                    let layout_idx = match msg.arguments[0] {
+                        osc::Argument::i(idx) if idx >= 0 => idx as usize,
+                        osc::Argument::s(name) =>
+                            match self.layouts.iter().position(|l| l.name == name) {
+                                Some(idx) => idx,
+                                None => return
+                            },
+                        _ => return
+                    };
+
+                    if layout_idx < self.layouts.len() {
+                        self.set_layout(maschine, layout_idx);
+                    }
+                }
+                _ => return
+            }
+        }
+        // `/midi_channel <channel>` sets the global channel, `/midi_channel/bank <channel>`
+        // the active bank's and `/midi_channel/pad <pad> <channel>` a pad's in the active
+        // bank. a channel of 0 takes a bank's or pad's channel away again.
+        else if path.starts_with("/midi_channel/bank") {
+            match msg.arguments.len() {
+                1 => {
+                    // 0 goes back to the global channel, anything else out of range is
+                    // ignored.
+                    self.banks[self.bank_idx].channel = match msg.arguments[0] {
+                        osc::Argument::i(0) => None,
+                        osc::Argument::i(n) if n > 0 => match channel_from_number(n as usize) {
+                            Some(channel) => Some(channel),
+                            None => return
+                        },
+                        _ => return
+                    };
+                }
+                _ => return
+            }
+        }
+        else if path.starts_with("/midi_channel/pad") {
+            match msg.arguments.len() {
+                2 => {
+                    let pad = match msg.arguments[0] {
+                        osc::Argument::i(pad) if pad >= 0 && pad < 16 => pad as usize,
+                        _ => return
+                    };
+
+                    // 0 goes back to the bank's channel, as with /midi_channel/bank.
+                    self.banks[self.bank_idx].pad_channels[pad] = match msg.arguments[1] {
+                        osc::Argument::i(0) => None,
+                        osc::Argument::i(n) if n > 0 => match channel_from_number(n as usize) {
+                            Some(channel) => Some(channel),
+                            None => return
+                        },
+                        _ => return
+                    };
+                }
+                _ => return
+            }
+        }
+        else if path.starts_with("/midi_channel") {
+            match msg.arguments.len() {
+                1 => {
+                    if let osc::Argument::i(channel) = msg.arguments[0] {
+                        if let Some(channel) = channel_from_number(channel.max(0) as usize) {
+                            self.channel = channel;
+                        }
+                    }
+                }
+                _ => return
+            }
+        }
+        else if path.starts_with("/bank") {
+            match msg.arguments.len() {
+                1 => {
+                    let bank_idx = match msg.arguments[0] {
+                        osc::Argument::i(idx) if idx >= 0 && (idx as usize) < self.banks.len() =>
+                            idx as usize,
+                        osc::Argument::s(name) => match bank_by_name(name) {
+                            Some(idx) => idx,
+                            None => return
+                        },
+                        _ => return
+                    };
+
+                    self.select_bank(maschine, bank_idx);
+                }
+                _ => return
+            }
+        }
+        else if path.starts_with("/scale") {
+            match msg.arguments.len() {
+                1 => {
+                    if let osc::Argument::s(name) = msg.arguments[0] {
+                        let scale_idx = match name {
+                            "off" => None,
+                            _ => match scale_by_name(name) {
+                                Some(idx) => Some(idx),
+                                None => return
+                            }
+                        };
+
+                        let root = self.root;
+                        self.set_key(maschine, scale_idx, root);
+                    }
+                }
+                _ => return
+            }
+        }
+        else if path.starts_with("/root") {
+            match msg.arguments.len() {
+                1 => {
+                    if let Some(root) = match msg.arguments[0] {
+                        osc::Argument::s(name) => parse_root(name),
+                        osc::Argument::i(root) if root >= 0 => Some((root % 12) as u8),
+                        _ => None
+                    } {
+                        let scale_idx = self.scale_idx;
+                        self.set_key(maschine, scale_idx, root);
+                    }
+                }
+                _ => return
+            }
+        }
+        else if path.starts_with("/raw_pads") {
+            match msg.arguments.len() {
+                1 => {
+                    if let osc::Argument::i(enable) = msg.arguments[0] {
+                        self.send_raw_pads = enable != 0;
+                    }
+                }
+                _ => return
+            }
+        }
+        else if path.starts_with("/timestamps") {
+            match msg.arguments.len() {
+                1 => {
+                    if let osc::Argument::i(enable) = msg.arguments[0] {
+                        self.send_timestamps = enable != 0;
+                    }
+                }
+                _ => return
+            }
+        }
 
     }
 
@@ -301,36 +903,175 @@ impl<'a> MHandler<'a> {
         }
     }
 
-    fn send_osc_button_msg(&self, btn: MaschineButton, status: usize) {
-        self.send_osc_msg(
-            &*format!("/maschine/button/{}", btn_to_osc_button_map(btn)),
-            osc_args![status as i32]);
+    // two int arguments: the seconds and microseconds since startup at which the device
+    // report was read.
+    fn push_osc_timestamp(&self, arguments: &mut Vec<osc::Argument>, time: Instant) {
+        let since_epoch = time.duration_since(self.epoch);
+
+        arguments.push(osc::Argument::i(since_epoch.as_secs() as i32));
+        arguments.push(osc::Argument::i(since_epoch.subsec_micros() as i32));
+    }
+
+    // messages describing an input event only end with a timestamp once it's been turned on
+    // with `/timestamps`, so that clients going by the number of arguments keep working.
+    fn send_osc_event_msg(&self, path: &str, mut arguments: Vec<osc::Argument>, time: Instant) {
+        if self.send_timestamps {
+            self.push_osc_timestamp(&mut arguments, time);
+        }
+
+        self.send_osc_msg(path, arguments);
+    }
+
+    fn send_osc_button_msg(&self, btn: MaschineButton, status: usize, time: Instant) {
+        self.send_osc_event_msg(
+            &*format!("{}/button/{}", self.osc_prefix, btn_to_osc_button_map(btn)),
+            osc_args![status as i32], time);
+    }
+
+    fn send_osc_button_gesture_msg(&self, btn: MaschineButton, gesture: &str, time: Instant) {
+        self.send_osc_event_msg(
+            &*format!("{}/button/{}/{}", self.osc_prefix, btn_to_osc_button_map(btn),
+                      gesture),
+            osc_args![1i32], time);
+    }
+
+    fn send_osc_combo_msg(&self, modifier: MaschineButton, btn: MaschineButton, time: Instant) {
+        self.send_osc_event_msg(
+            &*format!("{}/combo/{}/{}", self.osc_prefix, btn_to_osc_button_map(modifier),
+                      btn_to_osc_button_map(btn)),
+            osc_args![1i32], time);
+    }
+
+    fn send_osc_pad_combo_msg(&self, modifier: MaschineButton, pad_idx: usize, pressure: f32,
+                              time: Instant) {
+        self.send_osc_event_msg(
+            &*format!("{}/combo/{}/pad", self.osc_prefix, btn_to_osc_button_map(modifier)),
+            vec![osc::Argument::i(pad_idx as i32), osc::Argument::f(pressure)], time);
+    }
+
+    fn send_osc_encoder_msg(&self, delta: i32, time: Instant) {
+        self.send_osc_event_msg(&*format!("{}/encoder", self.osc_prefix), osc_args![delta], time);
+    }
+
+    // the raw stream is asked for separately anyway, so it always carries the timestamp.
+    fn send_osc_pads_raw_msg(&self, values: &[u16], time: Instant) {
+        let mut arguments: Vec<_> = values.iter()
+            .map(|&val| osc::Argument::i(val as i32))
+            .collect();
+
+        self.push_osc_timestamp(&mut arguments, time);
+        self.send_osc_msg(&*format!("{}/pads/raw", self.osc_prefix), arguments);
+    }
+}
+
+fn recv_osc_msg(osc_socket: &UdpSocket, controllers: &mut [Controller]) {
+    let mut buf = [0u8; 128];
+
+    let nbytes = match osc_socket.recv_from(&mut buf) {
+        Ok((nbytes, _)) => nbytes,
+        Err(e) => {
+            println!(" :: error in recv_from(): {}", e);
+            return;
+        }
+    };
+
+    let msg = match osc::Message::deserialize(&buf[.. nbytes]) {
+        Ok(msg) => msg,
+        Err(_) => {
+            println!(" :: couldn't decode OSC message :c");
+            return;
+        }
+    };
+
+    for ctl in controllers.iter_mut() {
+        let prefix_len = ctl.handler.osc_prefix.len();
+
+        if msg.path.starts_with(&*ctl.handler.osc_prefix)
+            && msg.path[prefix_len ..].starts_with("/") {
+            ctl.handler.handle_osc_messge(&mut *ctl.dev, &msg.path[prefix_len ..], &msg);
+            return;
+        }
+    }
+}
+
+// a port showing up after we've started might be one of the --connect targets coming back,
+// e.g. a synth which was restarted. its client's ClientStart is no use for this, since that
+// arrives before the client has created any ports.
+fn reconnect(seq_handle: &SequencerHandle, connect: &[String], controllers: &[Controller],
+             address: Address) {
+    if !connect.iter().any(|target| resolve_target(seq_handle, target) == Some(address)) {
+        return;
     }
 
-    fn send_osc_encoder_msg(&self, delta: i32) {
-        self.send_osc_msg("/maschine/encoder", osc_args![delta]);
+    for port in controllers.iter().filter_map(|ctl| ctl.handler.midi.seq_port()) {
+        if let Err(e) = port.connect_to(address) {
+            println!(" :: couldn't reconnect to {}:{}: {}", address.client, address.port, e);
+        }
     }
 }
 
-const PAD_NOTE_MAP: [U7; 16] = [
-    12, 13, 14, 15,
-     8,  9, 10, 11,
-     4,  5,  6,  7,
-     0,  1,  2,  3
-];
+// called when poll() says there's input, so the first read doesn't block. after that, only
+// the events which came in along with it are handled, since reading any further would wait
+// for the next one.
+fn recv_seq_events(seq_handle: &SequencerHandle, connect: &[String],
+                   controllers: &mut [Controller]) {
+    loop {
+        match seq_handle.recv_event() {
+            Ok(Some((port_nr, Event::Midi(msg)))) => route_feedback(controllers, port_nr, &msg),
+
+            Ok(Some((_, Event::PortStart(address)))) =>
+                reconnect(seq_handle, connect, controllers, address),
+
+            Ok(_) => {},
+            Err(e) => {
+                println!(" :: error receiving MIDI: {}", e);
+                return;
+            }
+        }
+
+        if seq_handle.input_pending() == 0 {
+            return;
+        }
+    }
+}
 
 impl<'a> MaschineHandler for MHandler<'a> {
-    fn pad_pressed(&mut self, maschine: &mut dyn Maschine, pad_idx: usize, pressure: f32) {
-        let midi_note = maschine.get_midi_note_base() + PAD_NOTE_MAP[pad_idx];
-        let msg = Message::NoteOn(Ch1, midi_note, self.pressure_to_vel(pressure));
+    fn pad_pressed(&mut self, maschine: &mut dyn Maschine, pad_idx: usize, pressure: f32,
+                   time: Instant) {
+        // with group held, pads 1-8 pick the bank instead of playing.
+        if self.held_buttons.contains(&MaschineButton::Group) {
+            if pad_number(pad_idx) < self.banks.len() {
+                self.select_bank(maschine, pad_number(pad_idx));
+            }
 
-        self.seq_port.send_message(&msg).unwrap();
-        self.seq_handle.drain_output();
+            return;
+        }
+
+        // a pad hit with any other modifier held is only a combo (see pad_combo()).
+        if MODIFIERS.iter().any(|modifier| self.held_buttons.contains(modifier)) {
+            return;
+        }
 
         maschine.set_pad_light(pad_idx, self.pad_color(), pressure.sqrt());
+
+        let midi_note = match self.pad_note(maschine, pad_idx) {
+            Some(note) => note,
+            None => return
+        };
+
+        let channel = self.pad_channel(pad_idx);
+
+        let msg = Message::NoteOn(channel, midi_note, self.pressure_to_vel(pressure));
+        // the note only counts as held if it went out, so that there's no note off or
+        // pressure for it later.
+        match self.midi.send_high_res(&msg, self.shape_pressure(pressure), time) {
+            Ok(_) => self.held_notes[pad_idx] = Some((channel, midi_note)),
+            Err(e) => println!(" :: error sending MIDI: {}", e)
+        }
     }
 
-    fn pad_aftertouch(&mut self, maschine: &mut dyn Maschine, pad_idx: usize, pressure: f32) {
+    fn pad_aftertouch(&mut self, maschine: &mut dyn Maschine, pad_idx: usize, pressure: f32,
+                      time: Instant) {
         match self.pressure_shape {
             PressureShape::Constant(_) => return,
             _ => {}
@@ -340,85 +1081,263 @@ impl<'a> MaschineHandler for MHandler<'a> {
             return
         }
 
-        let midi_note = maschine.get_midi_note_base() + PAD_NOTE_MAP[pad_idx];
-        let msg = Message::PolyphonicPressure(Ch1, midi_note,
+        let (channel, midi_note) = match self.held_notes[pad_idx] {
+            Some(held) => held,
+            None => return
+        };
+
+        let msg = Message::PolyphonicPressure(channel, midi_note,
                                               self.pressure_to_vel(pressure));
 
-        self.seq_port.send_message(&msg).unwrap();
-        self.seq_handle.drain_output();
+        if let Err(e) = self.midi.send_high_res(&msg, self.shape_pressure(pressure), time) {
+            println!(" :: error sending MIDI: {}", e);
+        }
 
         maschine.set_pad_light(pad_idx, self.pad_color(), pressure.sqrt());
     }
 
-    fn pad_released(&mut self, maschine: &mut dyn Maschine, pad_idx: usize) {
-        let midi_note = maschine.get_midi_note_base() + PAD_NOTE_MAP[pad_idx];
-        let msg = Message::NoteOff(Ch1, midi_note, 0);
-        self.seq_port.send_message(&msg).unwrap();
-        self.seq_handle.drain_output();
+    fn pad_released(&mut self, maschine: &mut dyn Maschine, pad_idx: usize, time: Instant) {
+        if let Some((channel, midi_note)) = self.held_notes[pad_idx].take() {
+            let msg = Message::NoteOff(channel, midi_note, 0);
+            if let Err(e) = self.midi.send(&msg, time) {
+                println!(" :: error sending MIDI: {}", e);
+            }
+        }
 
-        maschine.set_pad_light(pad_idx, self.pad_color(), PAD_RELEASED_BRIGHTNESS);
+        self.light_idle_pad(maschine, pad_idx);
     }
 
-    fn encoder_step(&mut self, _: &mut dyn Maschine, _: usize, delta: i32) {
-        self.send_osc_encoder_msg(delta);
+    fn pads_raw(&mut self, _: &mut dyn Maschine, values: &[u16], time: Instant) {
+        if self.send_raw_pads {
+            self.send_osc_pads_raw_msg(values, time);
+        }
     }
 
-    fn button_down(&mut self, _: &mut dyn Maschine, btn: MaschineButton) {
-        self.send_osc_button_msg(btn, 1);
+    // with f1 held the encoder picks the root, with f2 held the scale. otherwise it goes
+    // out over OSC, and as a CC if the bank has one for it.
+    fn encoder_step(&mut self, maschine: &mut dyn Maschine, _: usize, delta: i32,
+                    time: Instant) {
+        if self.held_buttons.contains(&MaschineButton::F1) {
+            self.step_root(maschine, delta);
+            return;
+        } else if self.held_buttons.contains(&MaschineButton::F2) {
+            self.step_scale(maschine, delta);
+            return;
+        }
+
+        self.send_osc_encoder_msg(delta, time);
+
+        let channel = self.bank().channel.unwrap_or(self.channel);
+
+        if let Some(ref mut encoder) = self.banks[self.bank_idx].encoder {
+            if let Some(value) = encoder.step(delta) {
+                let msg = Message::ControlChange(channel, encoder.controller, value);
+                if let Err(e) = self.midi.send(&msg, time) {
+                    println!(" :: error sending MIDI: {}", e);
+                }
+            }
+        }
     }
 
-    fn button_up(&mut self, _: &mut dyn Maschine, btn: MaschineButton) {
-        self.send_osc_button_msg(btn, 0);
+    fn button_down(&mut self, _: &mut dyn Maschine, btn: MaschineButton, time: Instant) {
+        self.held_buttons.push(btn);
+        self.send_osc_button_msg(btn, 1, time);
     }
-}
 
-fn main() {
-    let args: Vec<_> = env::args().collect();
+    fn button_up(&mut self, _: &mut dyn Maschine, btn: MaschineButton, time: Instant) {
+        self.held_buttons.retain(|&held| held != btn);
+        self.send_osc_button_msg(btn, 0, time);
+    }
+
+    fn button_long_press(&mut self, _: &mut dyn Maschine, btn: MaschineButton, time: Instant) {
+        self.send_osc_button_gesture_msg(btn, "long", time);
+    }
+
+    fn button_double_press(&mut self, _: &mut dyn Maschine, btn: MaschineButton,
+                           time: Instant) {
+        self.send_osc_button_gesture_msg(btn, "double", time);
+    }
+
+    fn button_combo(&mut self, maschine: &mut dyn Maschine, modifier: MaschineButton,
+                    btn: MaschineButton, time: Instant) {
+        if modifier == MaschineButton::Shift && btn == MaschineButton::PadMode {
+            let next = self.layout_idx + 1;
+            self.set_layout(maschine, next);
+        }
+
+        self.send_osc_combo_msg(modifier, btn, time);
+    }
+
+    fn pad_combo(&mut self, _: &mut dyn Maschine, modifier: MaschineButton, pad_idx: usize,
+                 pressure: f32, time: Instant) {
+        self.send_osc_pad_combo_msg(modifier, pad_idx, pressure, time);
+    }
 
-    if args.len() != 2 {
-        usage(&args[0]);
-        panic!("missing hidraw device path");
+    // pads hit together in one report go out to the synth together, in a single write.
+    fn report_processed(&mut self, _: &mut dyn Maschine, _: Instant) {
+        if let Err(e) = self.midi.flush() {
+            println!(" :: error sending MIDI: {}", e);
+        }
     }
+}
 
-    let dev_fd = match fcntl::open(Path::new(&args[1]), O_RDWR | O_NONBLOCK,
+fn open_device(path: &str) -> Box<dyn Maschine> {
+    let dev_fd = match fcntl::open(Path::new(path), O_RDWR | O_NONBLOCK,
                                    sys::stat::Mode::empty()) {
-        Err(err) => panic!("couldn't open {}: {}", args[1],
+        Err(err) => panic!("couldn't open {}: {}", path,
                            err.errno().desc()),
         Ok(file) => file
     };
 
-    let osc_socket = UdpSocket::bind("127.0.0.1:42434").unwrap();
+    Box::new(devices::mk2::Mikro::new(dev_fd))
+}
 
-    let seq_handle = SequencerHandle::open("maschine.rs", HandleOpenStreams::Output).unwrap();
-    let seq_port = seq_handle.create_port(
-        "Pads MIDI", PortCapabilities::PORT_CAPABILITY_READ | PortCapabilities::PORT_CAPABILITY_SUBS_READ, PortType::MidiGeneric)
-            .unwrap();
+fn main() {
+    let args: Vec<_> = env::args().collect();
+
+    let opts = match parse_args(&args[1 ..]) {
+        Ok(opts) => opts,
+        Err(e) => {
+            usage(&args[0]);
+            panic!("{}", e);
+        }
+    };
 
-    let mut dev = devices::mk2::Mikro::new(dev_fd);
+    // the sequencer is only needed for the ALSA backend, so that the others work on machines
+    // without it.
+    let seq_handle = if opts.midi == MidiBackend::Alsa || opts.list_ports {
+        // left blocking, so that sending waits for room rather than failing when the output
+        // buffer fills up. input is only read once poll() says it's there (see
+        // recv_seq_events()).
+        Some(SequencerHandle::open("maschine.rs", HandleOpenStreams::Duplex).unwrap())
+    } else {
+        None
+    };
 
-    let mut handler = MHandler {
-        color: HSL {
-            h: 0.0,
-            s: 1.0,
-            l: 0.3
-        },
+    if opts.list_ports {
+        list_ports(seq_handle.as_ref().unwrap());
+        return;
+    }
+
+    let config = match opts.config {
+        Some(ref path) => Config::load(Path::new(path)).unwrap_or_else(|e| panic!("{}", e)),
+        None => Config::default()
+    };
 
-        seq_port: &seq_port,
-        seq_handle: &seq_handle,
+    let (layouts, layout_idx) = layouts_from_config(&config).unwrap_or_else(|e| panic!("{}", e));
+    let (scale_idx, root) = key_from_config(&config).unwrap_or_else(|e| panic!("{}", e));
+    let banks = banks_from_config(&config).unwrap_or_else(|e| panic!("{}", e));
+    let channel = global_channel_from_config(&config).unwrap_or_else(|e| panic!("{}", e));
 
-        pressure_shape: PressureShape::Exponential(0.4),
-        send_aftertouch: false,
+    let dev_paths = &opts.dev_paths;
+    let multiple = dev_paths.len() > 1;
 
-        osc_socket: &osc_socket,
-        osc_outgoing_addr: SocketAddr::V4(
-            SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 42435))
+    let osc_socket = UdpSocket::bind("127.0.0.1:42434").unwrap();
+
+    // the raw MIDI (or UMP) device is opened once and shared between all the controllers.
+    let raw_midi_file = match opts.midi {
+        MidiBackend::RawMidi(ref path) | MidiBackend::Ump(ref path) =>
+            Some(OpenOptions::new().append(true).open(path).unwrap()),
+        _ => None
     };
 
-    dev.clear_screen();
+    // lets us notice --connect targets disappearing and coming back.
+    let _announce_port = seq_handle.as_ref()
+        .map(|seq_handle| seq_handle.subscribe_announcements().unwrap());
+
+    let epoch = Instant::now();
+
+    let mut controllers: Vec<_> = dev_paths.iter()
+        .enumerate()
+        .map(|(idx, path)| {
+            let port_name = |name: &str| {
+                if multiple { format!("{} {}", name, idx + 1) } else { name.to_string() }
+            };
+
+            let (midi, feedback_port): (Box<dyn MidiSink>, _) = match opts.midi {
+                MidiBackend::Alsa => {
+                    let seq_handle = seq_handle.as_ref().unwrap();
+
+                    let pads_port = create_pads_port(seq_handle, &port_name("Pads MIDI"),
+                                                     &opts.connect);
+                    let feedback_port = create_feedback_port(seq_handle,
+                                                             &port_name("Feedback MIDI"));
+
+                    (Box::new(AlsaSink::new(pads_port)), Some(feedback_port))
+                },
+
+                MidiBackend::RawMidi(_) => {
+                    let file = raw_midi_file.as_ref().unwrap().try_clone().unwrap();
+                    (Box::new(RawMidiSink::new(file)), None)
+                },
+
+                // each controller sends on a UMP group of its own.
+                MidiBackend::Ump(_) => {
+                    let file = raw_midi_file.as_ref().unwrap().try_clone().unwrap();
+                    (Box::new(UmpSink::new(file, (idx % 16) as u8)), None)
+                },
+
+                // each controller gets a session of its own.
+                MidiBackend::RtpMidi(peer) =>
+                    (Box::new(RtpMidiSink::connect(peer, &port_name("maschine.rs")).unwrap()),
+                     None),
+
+                #[cfg(feature = "jack")]
+                MidiBackend::Jack =>
+                    (Box::new(JackSink::new(&port_name("maschine.rs"), "pads").unwrap()), None),
+
+                MidiBackend::Null => (Box::new(NullSink), None)
+            };
+
+            let handler = MHandler {
+                midi: midi,
+                feedback_port: feedback_port,
+
+                pressure_shape: PressureShape::Exponential(0.4),
+                send_aftertouch: false,
+                send_raw_pads: false,
+                send_timestamps: false,
+
+                layouts: layouts.clone(),
+                layout_idx: layout_idx,
+
+                channel: channel,
+
+                banks: banks.clone(),
+                bank_idx: 0,
+
+                scale_idx: scale_idx,
+                root: root,
+
+                held_buttons: Vec::new(),
+                held_notes: [None; 16],
+
+                osc_prefix: if multiple {
+                    format!("/maschine/{}", idx + 1)
+                } else {
+                    "/maschine".to_string()
+                },
+
+                epoch: epoch,
+
+                osc_socket: &osc_socket,
+                osc_outgoing_addr: SocketAddr::V4(
+                    SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 42435))
+            };
+
+            Controller {
+                dev: open_device(path),
+                handler: handler
+            }
+        })
+        .collect();
+
+    for ctl in controllers.iter_mut() {
+        ctl.dev.clear_screen();
 
-    for i in 0..16 {
-        dev.set_pad_light(i, handler.pad_color(), PAD_RELEASED_BRIGHTNESS);
+        // lights the pads and the group button, and fills in the screen.
+        ctl.handler.select_bank(&mut *ctl.dev, 0);
     }
 
-    ev_loop(&mut dev, &mut handler);
+    ev_loop(&mut controllers, &osc_socket, seq_handle.as_ref(), &opts.connect);
 }
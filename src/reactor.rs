@@ -0,0 +1,207 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+use std::{io, ptr};
+
+use libc;
+use nix;
+use nix::Errno;
+use nix::poll::*;
+
+// identifies a source to `Reactor::remove()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SourceId(usize);
+
+type Callback<'a, C> = Box<dyn FnMut(&mut C) -> io::Result<()> + 'a>;
+
+struct Source<'a, C> {
+    id: SourceId,
+    fd: RawFd,
+
+    // timer sources own their (timerfd) fd and have to be read to be re-armed.
+    is_timer: bool,
+
+    callback: Callback<'a, C>
+}
+
+// a small poll()-based event loop. components register fds and periodic timers along with a
+// callback, and every callback gets mutable access to a shared context `C` when it fires. a
+// callback which returns an error has its source removed.
+pub struct Reactor<'a, C> {
+    sources: Vec<Source<'a, C>>,
+    next_id: usize
+}
+
+impl<'a, C> Reactor<'a, C> {
+    pub fn new() -> Self {
+        Reactor {
+            sources: Vec::new(),
+            next_id: 0
+        }
+    }
+
+    fn next_id(&mut self) -> SourceId {
+        self.next_id += 1;
+        SourceId(self.next_id)
+    }
+
+    // `callback` is run whenever `fd` is readable, and one last time when it hangs up or
+    // fails (an unplugged device, say), after which the source is removed. the fd is still
+    // owned by the caller and has to outlive the source.
+    pub fn add_fd<F>(&mut self, fd: RawFd, callback: F) -> SourceId
+        where F: FnMut(&mut C) -> io::Result<()> + 'a {
+        let id = self.next_id();
+
+        self.sources.push(Source {
+            id: id,
+            fd: fd,
+            is_timer: false,
+            callback: Box::new(callback)
+        });
+
+        id
+    }
+
+    // `callback` is run every `interval`, measured against the monotonic clock.
+    pub fn add_timer<F>(&mut self, interval: Duration, callback: F) -> io::Result<SourceId>
+        where F: FnMut(&mut C) -> io::Result<()> + 'a {
+        let timespec = libc::timespec {
+            tv_sec: interval.as_secs() as libc::time_t,
+            tv_nsec: interval.subsec_nanos() as libc::c_long
+        };
+
+        let spec = libc::itimerspec {
+            it_interval: timespec,
+            it_value: timespec
+        };
+
+        unsafe {
+            let fd = libc::timerfd_create(libc::CLOCK_MONOTONIC,
+                                          libc::TFD_NONBLOCK | libc::TFD_CLOEXEC);
+
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if libc::timerfd_settime(fd, 0, &spec, ptr::null_mut()) < 0 {
+                let err = io::Error::last_os_error();
+                libc::close(fd);
+                return Err(err);
+            }
+
+            let id = self.next_id();
+
+            self.sources.push(Source {
+                id: id,
+                fd: fd,
+                is_timer: true,
+                callback: Box::new(callback)
+            });
+
+            Ok(id)
+        }
+    }
+
+    // stops watching a source. a timer's fd is closed, an fd added with `add_fd()` is left
+    // alone.
+    pub fn remove(&mut self, id: SourceId) {
+        if let Some(idx) = self.sources.iter().position(|source| source.id == id) {
+            let source = self.sources.remove(idx);
+
+            if source.is_timer {
+                unsafe {
+                    libc::close(source.fd);
+                }
+            }
+        }
+    }
+
+    // waits for at least one source to become ready and runs the callbacks of all the ready
+    // ones, in the order in which they were added. sources which hung up or failed, or whose
+    // callback returned an error, are removed afterwards, since poll() would otherwise keep
+    // returning straight away for them.
+    pub fn turn(&mut self, ctx: &mut C) -> io::Result<()> {
+        let mut fds: Vec<_> = self.sources.iter()
+            .map(|source| PollFd::new(source.fd, POLLIN, EventFlags::empty()))
+            .collect();
+
+        loop {
+            match poll(&mut fds, -1) {
+                Ok(_) => break,
+
+                // a signal came in before anything was ready.
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+
+                Err(err) => return Err(io::Error::from_raw_os_error(err.errno() as i32))
+            }
+        }
+
+        let mut failed_sources = Vec::new();
+
+        for (source, fd) in self.sources.iter_mut().zip(fds.iter()) {
+            let revents = fd.revents().unwrap_or(EventFlags::empty());
+            let failed = revents.intersects(POLLHUP | POLLERR | POLLNVAL);
+
+            if !failed && !revents.contains(POLLIN) {
+                continue;
+            }
+
+            if source.is_timer && !failed {
+                // the number of expirations since the last read. we don't care how many
+                // there were, only that the timer fired.
+                let mut expirations = 0u64;
+
+                unsafe {
+                    libc::read(source.fd, &mut expirations as *mut u64 as *mut libc::c_void, 8);
+                }
+            }
+
+            let result = (source.callback)(ctx);
+
+            if failed || result.is_err() {
+                failed_sources.push(source.id);
+            }
+        }
+
+        for id in failed_sources {
+            self.remove(id);
+        }
+
+        Ok(())
+    }
+
+    // runs until there's nothing left to wait for, or until poll() fails.
+    pub fn run(&mut self, ctx: &mut C) -> io::Result<()> {
+        while !self.sources.is_empty() {
+            self.turn(ctx)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, C> Drop for Reactor<'a, C> {
+    fn drop(&mut self) {
+        for source in self.sources.iter().filter(|source| source.is_timer) {
+            unsafe {
+                libc::close(source.fd);
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use midi::U7;
+
+use config::Config;
+use layout::pad_number;
+
+pub struct Scale {
+    pub name: &'static str,
+
+    // semitones up from the root, within one octave.
+    pub intervals: &'static [u8]
+}
+
+pub const SCALES: [Scale; 12] = [
+    Scale { name: "major", intervals: &[0, 2, 4, 5, 7, 9, 11] },
+    Scale { name: "minor", intervals: &[0, 2, 3, 5, 7, 8, 10] },
+    Scale { name: "dorian", intervals: &[0, 2, 3, 5, 7, 9, 10] },
+    Scale { name: "phrygian", intervals: &[0, 1, 3, 5, 7, 8, 10] },
+    Scale { name: "lydian", intervals: &[0, 2, 4, 6, 7, 9, 11] },
+    Scale { name: "mixolydian", intervals: &[0, 2, 4, 5, 7, 9, 10] },
+    Scale { name: "locrian", intervals: &[0, 1, 3, 5, 6, 8, 10] },
+    Scale { name: "harmonic minor", intervals: &[0, 2, 3, 5, 7, 8, 11] },
+    Scale { name: "melodic minor", intervals: &[0, 2, 3, 5, 7, 9, 11] },
+    Scale { name: "major pentatonic", intervals: &[0, 2, 4, 7, 9] },
+    Scale { name: "minor pentatonic", intervals: &[0, 3, 5, 7, 10] },
+    Scale { name: "blues", intervals: &[0, 3, 5, 6, 7, 10] }
+];
+
+pub const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"
+];
+
+pub fn scale_by_name(name: &str) -> Option<usize> {
+    SCALES.iter().position(|scale| scale.name == name)
+}
+
+// "C" through "B", with sharps or flats.
+pub fn parse_root(name: &str) -> Option<u8> {
+    if let Some(root) = NOTE_NAMES.iter().position(|&n| n.eq_ignore_ascii_case(name)) {
+        return Some(root as u8);
+    }
+
+    if name.len() == 2 && (name.ends_with('b') || name.ends_with('B')) {
+        return parse_root(&name[.. 1]).map(|natural| (natural + 11) % 12);
+    }
+
+    None
+}
+
+// the pads count up through the scale in the order they're numbered, like the chromatic
+// layout does through the semitones.
+fn scale_degree(pad_idx: usize) -> usize {
+    pad_number(pad_idx)
+}
+
+impl Scale {
+    // the bottom left pad plays `root` in the octave of `note_base`.
+    pub fn note(&self, root: u8, note_base: U7, pad_idx: usize) -> Option<U7> {
+        let degree = scale_degree(pad_idx);
+        let octave = (degree / self.intervals.len()) as u32;
+
+        let note = (note_base as u32 / 12) * 12 + root as u32
+            + octave * 12 + self.intervals[degree % self.intervals.len()] as u32;
+
+        if note <= 127 { Some(note as U7) } else { None }
+    }
+
+    pub fn is_root(&self, pad_idx: usize) -> bool {
+        scale_degree(pad_idx) % self.intervals.len() == 0
+    }
+}
+
+// the scale (none for the layout's own notes) and root to start with, from a top-level
+// "scale = ..." and "root = ..." in the config file.
+pub fn key_from_config(config: &Config) -> Result<(Option<usize>, u8), String> {
+    let scale_idx = match config.global().get("scale") {
+        Some(entry) if entry.value == "off" => None,
+        Some(entry) => match scale_by_name(&entry.value) {
+            Some(idx) => Some(idx),
+            None => return Err(format!("line {}: no scale called {}", entry.line, entry.value))
+        },
+
+        None => None
+    };
+
+    let root = match config.global().get("root") {
+        Some(entry) => match parse_root(&entry.value) {
+            Some(root) => root,
+            None => return Err(format!("line {}: bad root note {}", entry.line, entry.value))
+        },
+
+        None => 0
+    };
+
+    Ok((scale_idx, root))
+}
@@ -0,0 +1,59 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use std::error::Error;
+use std::time::Instant;
+
+use midi::Message;
+use alsa_seq::{
+    SequencerHandle,
+    SequencerPort
+};
+
+use sink::MidiSink;
+
+// sends through a port of our ALSA sequencer client. messages collect in the handle's output
+// buffer and all go out together on `flush()`.
+pub struct AlsaSink {
+    seq_handle: SequencerHandle,
+    port: SequencerPort
+}
+
+impl AlsaSink {
+    pub fn new(port: SequencerPort) -> Self {
+        AlsaSink {
+            seq_handle: port.handle().clone(),
+            port: port
+        }
+    }
+}
+
+impl MidiSink for AlsaSink {
+    fn send(&mut self, msg: &Message, _: Instant) -> Result<(), Box<dyn Error>> {
+        self.port.send_message(msg)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.seq_handle.drain_output()?;
+        Ok(())
+    }
+
+    fn seq_port(&self) -> Option<&SequencerPort> {
+        Some(&self.port)
+    }
+}
@@ -0,0 +1,158 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use std::error::Error;
+use std::sync::mpsc::{
+    sync_channel,
+    Receiver,
+    SyncSender,
+    TrySendError
+};
+use std::time::Instant;
+
+use jack;
+use midi::Message;
+
+use sink::{
+    MidiSink,
+    encode_message
+};
+
+// how many messages can be waiting for the process callback before we start dropping them.
+const QUEUE_LENGTH: usize = 256;
+
+struct JackEvent {
+    // JACK's frame time at the moment the HID report was read.
+    frame: jack::Frames,
+
+    bytes: [u8; 3],
+    len: usize
+}
+
+// runs in JACK's realtime thread, so it mustn't block or allocate.
+struct JackProcess {
+    port: jack::Port<jack::MidiOut>,
+    events: Receiver<JackEvent>
+}
+
+impl jack::ProcessHandler for JackProcess {
+    fn process(&mut self, _: &jack::Client, ps: &jack::ProcessScope) -> jack::Control {
+        let cycle_start = ps.last_frame_time();
+        let n_frames = ps.n_frames();
+
+        let mut writer = self.port.writer(ps);
+        let mut last_offset = 0;
+
+        while let Ok(ev) = self.events.try_recv() {
+            // events have to be written in order, and anything which turns up later than a
+            // period after it happened goes out at the start of this one.
+            let offset = ev.frame.wrapping_add(n_frames).wrapping_sub(cycle_start) as i32;
+            let offset = (offset.max(0) as jack::Frames).min(n_frames - 1).max(last_offset);
+
+            let _ = writer.write(&jack::RawMidi {
+                time: offset,
+                bytes: &ev.bytes[.. ev.len]
+            });
+
+            last_offset = offset;
+        }
+
+        jack::Control::Continue
+    }
+}
+
+// a JACK client with a single MIDI out port. messages are stamped with the frame time of the
+// report they came from and handed to the process callback through a channel. the callback
+// plays them back one period later at the same offset, so the jitter between the device
+// being read and the next period starting doesn't end up in the timing.
+pub struct JackSink {
+    client: jack::AsyncClient<(), JackProcess>,
+    events: SyncSender<JackEvent>,
+
+    // messages thrown away since the queue last had room, while the process callback
+    // wasn't keeping up, e.g. with the JACK server stalled.
+    dropped: usize
+}
+
+impl JackSink {
+    pub fn new(client_name: &str, port_name: &str) -> Result<Self, jack::Error> {
+        let (client, _) = jack::Client::new(client_name, jack::ClientOptions::NO_START_SERVER)?;
+        let port = client.register_port(port_name, jack::MidiOut::default())?;
+
+        let (sender, receiver) = sync_channel(QUEUE_LENGTH);
+
+        let client = client.activate_async((), JackProcess {
+            port: port,
+            events: receiver
+        })?;
+
+        Ok(JackSink {
+            client: client,
+            events: sender,
+            dropped: 0
+        })
+    }
+
+    // JACK's clock and `Instant` both run off the monotonic clock, but their zeroes differ, so
+    // the conversion goes through how long ago `time` was.
+    fn frame_time(&self, time: Instant) -> jack::Frames {
+        let age = Instant::now().duration_since(time);
+        let age_us = age.as_secs() * 1_000_000 + (age.subsec_nanos() / 1000) as u64;
+
+        self.client.as_client().time_to_frames(jack::get_time().saturating_sub(age_us))
+    }
+}
+
+impl MidiSink for JackSink {
+    fn send(&mut self, msg: &Message, time: Instant) -> Result<(), Box<dyn Error>> {
+        let mut encoded = Vec::with_capacity(3);
+        encode_message(msg, &mut encoded)?;
+
+        if encoded.len() > 3 {
+            return Err("SysEx isn't supported by the JACK sink".into());
+        }
+
+        let mut ev = JackEvent {
+            frame: self.frame_time(time),
+            bytes: [0; 3],
+            len: encoded.len()
+        };
+
+        ev.bytes[.. encoded.len()].copy_from_slice(&encoded);
+
+        // a stalled JACK shouldn't take the pads down with it, so a full queue only costs
+        // the message. it's reported once things are moving again, rather than once per
+        // message.
+        match self.events.try_send(ev) {
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                Ok(())
+            },
+
+            Err(TrySendError::Disconnected(_)) => Err("JACK process callback has gone away".into()),
+
+            Ok(()) => {
+                if self.dropped > 0 {
+                    println!(" :: JACK MIDI queue was full, dropped {} messages", self.dropped);
+                    self.dropped = 0;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
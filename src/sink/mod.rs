@@ -0,0 +1,152 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use std::error::Error;
+use std::time::Instant;
+
+use midi::*;
+use alsa_seq::SequencerPort;
+
+mod alsa;
+mod rawmidi;
+mod rtpmidi;
+mod ump;
+
+#[cfg(feature = "jack")]
+mod jackmidi;
+
+#[cfg(test)]
+mod recording;
+
+pub use self::alsa::AlsaSink;
+pub use self::rawmidi::RawMidiSink;
+pub use self::rtpmidi::RtpMidiSink;
+pub use self::ump::UmpSink;
+
+#[cfg(feature = "jack")]
+pub use self::jackmidi::JackSink;
+
+#[cfg(test)]
+pub use self::recording::RecordingSink;
+
+#[cfg(test)]
+pub use self::ump::encode_ump;
+
+// wherever the MIDI generated from the pads ends up.
+#[allow(unused_variables)]
+pub trait MidiSink {
+    // `time` is when the report which caused the message was read from the device.
+    fn send(&mut self, msg: &Message, time: Instant) -> Result<(), Box<dyn Error>>;
+
+    // for note ons and polyphonic pressure from the pads. `value` is the velocity or pressure
+    // from 0.0 to 1.0 which `msg` was made from, for sinks which can carry more than its
+    // 7 bits. the rest just send `msg`.
+    fn send_high_res(&mut self, msg: &Message, value: f32, time: Instant)
+        -> Result<(), Box<dyn Error>> {
+        self.send(msg, time)
+    }
+
+    // sinks may hold on to messages until this is called, which happens once per report from
+    // the device.
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    // for sinks sending through the ALSA sequencer, the port they send from, so that it can
+    // be (re)connected.
+    fn seq_port(&self) -> Option<&SequencerPort> {
+        None
+    }
+}
+
+// throws everything away, for running without any MIDI output at all.
+pub struct NullSink;
+
+impl MidiSink for NullSink {
+    fn send(&mut self, _: &Message, _: Instant) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+const CHANNELS: [Channel; 16] = [
+    Ch1, Ch2, Ch3, Ch4, Ch5, Ch6, Ch7, Ch8,
+    Ch9, Ch10, Ch11, Ch12, Ch13, Ch14, Ch15, Ch16
+];
+
+fn channel_number(channel: Channel) -> u8 {
+    CHANNELS.iter().position(|&ch| ch == channel).unwrap() as u8
+}
+
+// `number` counts from 1, as channels are usually written.
+pub fn channel_from_number(number: usize) -> Option<Channel> {
+    match number {
+        1 ..= 16 => Some(CHANNELS[number - 1]),
+        _ => None
+    }
+}
+
+// appends the wire encoding of `msg` to `out`, always with a full status byte (no running
+// status), since the messages can end up interleaved with other sources. fails for the
+// (N)RPN messages, which are a run of controller changes rather than one message.
+pub fn encode_message(msg: &Message, out: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+    let status = |kind: u8, channel: Channel| kind | channel_number(channel);
+
+    match *msg {
+        Message::NoteOff(ch, note, velocity) =>
+            out.extend_from_slice(&[status(0x80, ch), note & 0x7F, velocity & 0x7F]),
+        Message::NoteOn(ch, note, velocity) =>
+            out.extend_from_slice(&[status(0x90, ch), note & 0x7F, velocity & 0x7F]),
+        Message::PolyphonicPressure(ch, note, pressure) =>
+            out.extend_from_slice(&[status(0xA0, ch), note & 0x7F, pressure & 0x7F]),
+        Message::ControlChange(ch, controller, value) =>
+            out.extend_from_slice(&[status(0xB0, ch), controller & 0x7F, value & 0x7F]),
+
+        // the channel mode messages are controller numbers 120 and up
+        Message::AllSoundOff(ch) => out.extend_from_slice(&[status(0xB0, ch), 0x78, 0]),
+        Message::ResetAllControllers(ch) => out.extend_from_slice(&[status(0xB0, ch), 0x79, 0]),
+        Message::LocalControlOff(ch) => out.extend_from_slice(&[status(0xB0, ch), 0x7A, 0]),
+        Message::LocalControlOn(ch) => out.extend_from_slice(&[status(0xB0, ch), 0x7A, 0x7F]),
+        Message::AllNotesOff(ch) => out.extend_from_slice(&[status(0xB0, ch), 0x7B, 0]),
+
+        Message::ProgramChange(ch, program) =>
+            out.extend_from_slice(&[status(0xC0, ch), program & 0x7F]),
+        Message::ChannelPressure(ch, pressure) =>
+            out.extend_from_slice(&[status(0xD0, ch), pressure & 0x7F]),
+        Message::PitchBend(ch, bend) =>
+            out.extend_from_slice(&[status(0xE0, ch), (bend & 0x7F) as u8,
+                                    ((bend >> 7) & 0x7F) as u8]),
+
+        Message::SysEx(manufacturer, ref data) => {
+            out.push(0xF0);
+            out.extend(manufacturer.to_u7s());
+            out.extend(data.iter().map(|&byte| byte & 0x7F));
+            out.push(0xF7);
+        },
+
+        Message::TimingClock => out.push(0xF8),
+        Message::Start => out.push(0xFA),
+        Message::Continue => out.push(0xFB),
+        Message::Stop => out.push(0xFC),
+        Message::ActiveSensing => out.push(0xFE),
+        Message::SystemReset => out.push(0xFF),
+
+        Message::RPN7(..) | Message::RPN14(..) | Message::NRPN7(..) | Message::NRPN14(..) =>
+            return Err(format!("can't encode {:?}", msg).into())
+    }
+
+    Ok(())
+}
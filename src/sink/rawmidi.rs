@@ -0,0 +1,62 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::time::Instant;
+
+use midi::Message;
+
+use sink::{
+    MidiSink,
+    encode_message
+};
+
+// writes plain MIDI bytes to a file, which is meant to be a raw MIDI device like
+// /dev/snd/midiC1D0 or /dev/midi1 (but a FIFO or a regular file will do as well).
+pub struct RawMidiSink {
+    file: File,
+    buf: Vec<u8>
+}
+
+impl RawMidiSink {
+    pub fn new(file: File) -> Self {
+        RawMidiSink {
+            file: file,
+            buf: Vec::new()
+        }
+    }
+}
+
+impl MidiSink for RawMidiSink {
+    fn send(&mut self, msg: &Message, _: Instant) -> Result<(), Box<dyn Error>> {
+        encode_message(msg, &mut self.buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        let result = self.file.write_all(&self.buf);
+        self.buf.clear();
+
+        result?;
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::rc::Rc;
+use std::time::Instant;
+
+use midi::Message;
+
+use sink::MidiSink;
+
+#[derive(Default)]
+struct Recording {
+    pending: Vec<Message>,
+    flushed: Vec<Message>
+}
+
+// keeps everything sent to it, for tests. clones share the same recording, so a clone can be
+// handed to a handler and the original used to look at what it sent.
+#[derive(Clone, Default)]
+pub struct RecordingSink {
+    recording: Rc<RefCell<Recording>>
+}
+
+impl RecordingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // messages which were sent, but not flushed yet.
+    pub fn pending(&self) -> Vec<Message> {
+        self.recording.borrow().pending.clone()
+    }
+
+    pub fn flushed(&self) -> Vec<Message> {
+        self.recording.borrow().flushed.clone()
+    }
+}
+
+impl MidiSink for RecordingSink {
+    fn send(&mut self, msg: &Message, _: Instant) -> Result<(), Box<dyn Error>> {
+        self.recording.borrow_mut().pending.push(msg.clone());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut recording = self.recording.borrow_mut();
+        let pending: Vec<_> = recording.pending.drain(..).collect();
+
+        recording.flushed.extend(pending);
+        Ok(())
+    }
+}
@@ -0,0 +1,421 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use std::error::Error;
+use std::io;
+use std::net::{
+    IpAddr,
+    SocketAddr,
+    UdpSocket
+};
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering
+};
+use std::thread::{
+    self,
+    JoinHandle
+};
+use std::time::{
+    Duration,
+    Instant,
+    SystemTime,
+    UNIX_EPOCH
+};
+
+use midi::Message;
+
+use sink::{
+    MidiSink,
+    encode_message
+};
+
+//
+// AppleMIDI session protocol, which runs alongside RTP-MIDI (RFC 6295) to set up and keep up
+// a session: an invitation exchange on both the control and the data port, then periodic
+// clock synchronisation on the data port.
+//
+
+const APPLEMIDI_SIGNATURE: [u8; 2] = [0xFF, 0xFF];
+const APPLEMIDI_VERSION: u32 = 2;
+
+const CMD_INVITATION: &[u8; 2] = b"IN";
+const CMD_ACCEPT: &[u8; 2] = b"OK";
+const CMD_REJECT: &[u8; 2] = b"NO";
+const CMD_END: &[u8; 2] = b"BY";
+const CMD_SYNC: &[u8; 2] = b"CK";
+
+const RTP_VERSION: u8 = 2 << 6;
+const RTP_MIDI_PAYLOAD_TYPE: u8 = 0x61;
+
+const HANDSHAKE_TIMEOUT_MS: u64 = 1000;
+const HANDSHAKE_ATTEMPTS: usize = 5;
+
+const SYNC_INTERVAL_SECS: u64 = 10;
+
+// session timestamps, both in the sync exchange and in the RTP header, count 100µs ticks.
+fn session_time(epoch: Instant, time: Instant) -> u64 {
+    if time < epoch {
+        return 0;
+    }
+
+    let since = time.duration_since(epoch);
+    since.as_secs() * 10_000 + (since.subsec_nanos() / 100_000) as u64
+}
+
+fn push_u32(buf: &mut Vec<u8>, val: u32) {
+    buf.extend_from_slice(&[(val >> 24) as u8, (val >> 16) as u8, (val >> 8) as u8, val as u8]);
+}
+
+fn push_u64(buf: &mut Vec<u8>, val: u64) {
+    push_u32(buf, (val >> 32) as u32);
+    push_u32(buf, val as u32);
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    ((buf[0] as u32) << 24) | ((buf[1] as u32) << 16) | ((buf[2] as u32) << 8) | buf[3] as u32
+}
+
+fn read_u64(buf: &[u8]) -> u64 {
+    ((read_u32(buf) as u64) << 32) | read_u32(&buf[4 ..]) as u64
+}
+
+// IN, OK, NO and BY all share this layout. the name is only sent with IN and OK.
+fn session_packet(command: &[u8; 2], token: u32, ssrc: u32, name: Option<&str>) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    buf.extend_from_slice(&APPLEMIDI_SIGNATURE);
+    buf.extend_from_slice(command);
+    push_u32(&mut buf, APPLEMIDI_VERSION);
+    push_u32(&mut buf, token);
+    push_u32(&mut buf, ssrc);
+
+    if let Some(name) = name {
+        buf.extend_from_slice(name.as_bytes());
+        buf.push(0);
+    }
+
+    buf
+}
+
+fn sync_packet(ssrc: u32, count: u8, timestamps: [u64; 3]) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    buf.extend_from_slice(&APPLEMIDI_SIGNATURE);
+    buf.extend_from_slice(CMD_SYNC);
+    push_u32(&mut buf, ssrc);
+    buf.extend_from_slice(&[count, 0, 0, 0]);
+
+    for &timestamp in timestamps.iter() {
+        push_u64(&mut buf, timestamp);
+    }
+
+    buf
+}
+
+enum SessionPacket {
+    Accept(u32),
+    Reject,
+    End,
+    Sync(u8, [u64; 3])
+}
+
+fn parse_session_packet(buf: &[u8]) -> Option<SessionPacket> {
+    if buf.len() < 4 || buf[0 .. 2] != APPLEMIDI_SIGNATURE {
+        return None;
+    }
+
+    let command = &buf[2 .. 4];
+
+    if command == CMD_SYNC {
+        if buf.len() < 36 {
+            return None;
+        }
+
+        let timestamps = [read_u64(&buf[12 ..]), read_u64(&buf[20 ..]), read_u64(&buf[28 ..])];
+        return Some(SessionPacket::Sync(buf[8], timestamps));
+    }
+
+    if buf.len() < 16 {
+        return None;
+    }
+
+    let token = read_u32(&buf[8 ..]);
+
+    match command {
+        _ if command == CMD_ACCEPT => Some(SessionPacket::Accept(token)),
+        _ if command == CMD_REJECT => Some(SessionPacket::Reject),
+        _ if command == CMD_END => Some(SessionPacket::End),
+        _ => None
+    }
+}
+
+// sends an invitation until the peer answers it (or we give up).
+fn invite(socket: &UdpSocket, peer: SocketAddr, token: u32, ssrc: u32, name: &str)
+    -> io::Result<()> {
+    let invitation = session_packet(CMD_INVITATION, token, ssrc, Some(name));
+    let mut buf = [0u8; 256];
+
+    socket.set_read_timeout(Some(Duration::from_millis(HANDSHAKE_TIMEOUT_MS)))?;
+
+    for _ in 0 .. HANDSHAKE_ATTEMPTS {
+        socket.send_to(&invitation, peer)?;
+
+        loop {
+            let len = match socket.recv_from(&mut buf) {
+                Ok((len, from)) if from == peer => len,
+                Ok(_) => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock
+                    || e.kind() == io::ErrorKind::TimedOut => break,
+                Err(e) => return Err(e)
+            };
+
+            match parse_session_packet(&buf[.. len]) {
+                Some(SessionPacket::Accept(t)) if t == token => return Ok(()),
+                Some(SessionPacket::Reject) =>
+                    return Err(io::Error::new(io::ErrorKind::ConnectionRefused,
+                                              "invitation rejected")),
+                _ => {}
+            }
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::TimedOut, "no answer to invitation"))
+}
+
+// AppleMIDI wants the data port to be right after the control port.
+fn bind_port_pair(ip: IpAddr) -> io::Result<(UdpSocket, UdpSocket)> {
+    let mut last_err = None;
+
+    for _ in 0 .. 16 {
+        let control = UdpSocket::bind(SocketAddr::new(ip, 0))?;
+        let data_port = control.local_addr()?.port().wrapping_add(1);
+
+        match UdpSocket::bind(SocketAddr::new(ip, data_port)) {
+            Ok(data) => return Ok((control, data)),
+            Err(e) => last_err = Some(e)
+        }
+    }
+
+    Err(last_err.unwrap())
+}
+
+// we don't need anything cryptographically random, just something unlikely to clash with
+// another session's.
+fn session_id(salt: u32) -> u32 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+    (now.subsec_nanos() ^ (now.as_secs() as u32).rotate_left(16) ^ process::id()).wrapping_add(salt)
+}
+
+// answers the peer's clock syncs and starts our own every SYNC_INTERVAL_SECS, which also
+// keeps the peer from timing the session out.
+fn maintain_session(data: UdpSocket, peer_data: SocketAddr, ssrc: u32, epoch: Instant,
+                    running: Arc<AtomicBool>) {
+    let interval = Duration::from_secs(SYNC_INTERVAL_SECS);
+    let mut last_sync: Option<Instant> = None;
+    let mut buf = [0u8; 256];
+
+    let _ = data.set_read_timeout(Some(Duration::from_millis(HANDSHAKE_TIMEOUT_MS)));
+
+    while running.load(Ordering::Relaxed) {
+        if last_sync.map_or(true, |last| last.elapsed() >= interval) {
+            let now = session_time(epoch, Instant::now());
+            let _ = data.send_to(&sync_packet(ssrc, 0, [now, 0, 0]), peer_data);
+
+            last_sync = Some(Instant::now());
+        }
+
+        let len = match data.recv_from(&mut buf) {
+            Ok((len, from)) if from == peer_data => len,
+            _ => continue
+        };
+
+        let now = session_time(epoch, Instant::now());
+
+        match parse_session_packet(&buf[.. len]) {
+            Some(SessionPacket::Sync(0, ts)) => {
+                let _ = data.send_to(&sync_packet(ssrc, 1, [ts[0], now, 0]), peer_data);
+            },
+
+            Some(SessionPacket::Sync(1, ts)) => {
+                let _ = data.send_to(&sync_packet(ssrc, 2, [ts[0], ts[1], now]), peer_data);
+            },
+
+            Some(SessionPacket::End) => return,
+            _ => {}
+        }
+    }
+}
+
+// delta times in the MIDI list are variable-length, seven bits to a byte, most significant
+// first.
+fn push_delta_time(buf: &mut Vec<u8>, delta: u32) {
+    let delta = delta & 0x0FFF_FFFF;
+
+    for shift in [21, 14, 7].iter() {
+        if delta >= 1 << shift {
+            buf.push(0x80 | (delta >> shift) as u8 & 0x7F);
+        }
+    }
+
+    buf.push(delta as u8 & 0x7F);
+}
+
+// an RTP-MIDI session, with us as the initiator. the messages sent between two flushes go
+// out together in one RTP packet, with the time between them kept in the delta times.
+pub struct RtpMidiSink {
+    control: UdpSocket,
+    data: UdpSocket,
+
+    peer_control: SocketAddr,
+    peer_data: SocketAddr,
+
+    token: u32,
+    ssrc: u32,
+    epoch: Instant,
+    sequence_nr: u16,
+
+    pending: Vec<(Instant, Vec<u8>)>,
+
+    running: Arc<AtomicBool>,
+    sync_thread: Option<JoinHandle<()>>
+}
+
+impl RtpMidiSink {
+    // `peer` is the address of the other side's control port. `name` is the session name
+    // it'll show for us.
+    pub fn connect(peer: SocketAddr, name: &str) -> io::Result<Self> {
+        let local_ip = match peer {
+            SocketAddr::V4(_) => "0.0.0.0".parse().unwrap(),
+            SocketAddr::V6(_) => "::".parse().unwrap()
+        };
+
+        let (control, data) = bind_port_pair(local_ip)?;
+        let peer_data = SocketAddr::new(peer.ip(), peer.port().wrapping_add(1));
+
+        let token = session_id(0);
+        let ssrc = session_id(token);
+
+        invite(&control, peer, token, ssrc, name)?;
+        invite(&data, peer_data, token, ssrc, name)?;
+
+        let epoch = Instant::now();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let sync_thread = {
+            let data = data.try_clone()?;
+            let running = running.clone();
+
+            thread::spawn(move || maintain_session(data, peer_data, ssrc, epoch, running))
+        };
+
+        Ok(RtpMidiSink {
+            control: control,
+            data: data,
+
+            peer_control: peer,
+            peer_data: peer_data,
+
+            token: token,
+            ssrc: ssrc,
+            epoch: epoch,
+            sequence_nr: 0,
+
+            pending: Vec::new(),
+
+            running: running,
+            sync_thread: Some(sync_thread)
+        })
+    }
+
+    fn rtp_packet(&self) -> Vec<u8> {
+        let start = self.pending[0].0;
+
+        let mut commands = Vec::new();
+        let mut last = start;
+
+        for (idx, &(time, ref bytes)) in self.pending.iter().enumerate() {
+            // the first command goes without a delta time (the Z flag is left clear).
+            if idx > 0 {
+                let time = if time > last { time } else { last };
+                push_delta_time(&mut commands, session_time(last, time) as u32);
+                last = time;
+            }
+
+            commands.extend_from_slice(bytes);
+        }
+
+        let mut buf = Vec::with_capacity(14 + commands.len());
+
+        buf.push(RTP_VERSION);
+        buf.push(RTP_MIDI_PAYLOAD_TYPE);
+        buf.extend_from_slice(&[(self.sequence_nr >> 8) as u8, self.sequence_nr as u8]);
+        push_u32(&mut buf, session_time(self.epoch, start) as u32);
+        push_u32(&mut buf, self.ssrc);
+
+        // MIDI command section header: no journal, and the short form of the length if the
+        // list fits in four bits.
+        if commands.len() <= 0x0F {
+            buf.push(commands.len() as u8);
+        } else {
+            buf.push(0x80 | (commands.len() >> 8) as u8 & 0x0F);
+            buf.push(commands.len() as u8);
+        }
+
+        buf.extend_from_slice(&commands);
+        buf
+    }
+}
+
+impl MidiSink for RtpMidiSink {
+    fn send(&mut self, msg: &Message, time: Instant) -> Result<(), Box<dyn Error>> {
+        let mut bytes = Vec::with_capacity(3);
+        encode_message(msg, &mut bytes)?;
+
+        self.pending.push((time, bytes));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let packet = self.rtp_packet();
+
+        self.pending.clear();
+        self.sequence_nr = self.sequence_nr.wrapping_add(1);
+
+        self.data.send_to(&packet, self.peer_data)?;
+        Ok(())
+    }
+}
+
+impl Drop for RtpMidiSink {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+
+        let bye = session_packet(CMD_END, self.token, self.ssrc, None);
+        let _ = self.control.send_to(&bye, self.peer_control);
+
+        if let Some(sync_thread) = self.sync_thread.take() {
+            let _ = sync_thread.join();
+        }
+    }
+}
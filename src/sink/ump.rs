@@ -0,0 +1,184 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::time::Instant;
+
+use midi::Message;
+
+use sink::{
+    MidiSink,
+    encode_message
+};
+
+//
+// MIDI 2.0 Universal MIDI Packets. channel voice messages go out as 64-bit MIDI 2.0 packets
+// (message type 0x4), with 16-bit note velocities and 32-bit controller/pressure values,
+// system realtime messages as 32-bit system packets (message type 0x1).
+//
+
+const UMP_SYSTEM: u32 = 0x1;
+const UMP_MIDI2_CHANNEL_VOICE: u32 = 0x4;
+
+// bank select isn't sent, so the program change's bank valid flag stays clear.
+const PROGRAM_CHANGE_NO_BANK: u32 = 0;
+
+// widens a `src_bits` value to `dst_bits` the way the MIDI 2.0 spec asks translators to, so
+// that the minimum, the center and the maximum all end up where they should.
+pub fn scale_up(value: u32, src_bits: u32, dst_bits: u32) -> u32 {
+    let value = value as u64 & ((1 << src_bits) - 1);
+    let scale_bits = dst_bits - src_bits;
+    let shifted = value << scale_bits;
+
+    if value <= 1 << (src_bits - 1) {
+        return shifted as u32;
+    }
+
+    // above the center, the bits below the top one get repeated down into the new ones.
+    let repeat_bits = src_bits - 1;
+    let mut repeat = value & ((1 << repeat_bits) - 1);
+
+    if scale_bits > repeat_bits {
+        repeat <<= scale_bits - repeat_bits;
+    } else {
+        repeat >>= repeat_bits - scale_bits;
+    }
+
+    let mut result = shifted;
+
+    while repeat != 0 {
+        result |= repeat;
+        repeat >>= repeat_bits;
+    }
+
+    result as u32
+}
+
+fn unit_to_u16(value: f32) -> u32 {
+    (value.clamp(0.0, 1.0) as f64 * 65535.0).round() as u32
+}
+
+fn unit_to_u32(value: f32) -> u32 {
+    (value.clamp(0.0, 1.0) as f64 * 4294967295.0).round() as u32
+}
+
+// appends `msg` to `out` as UMP words, on UMP group `group`. `value`, if there is one, is
+// the note on velocity or polyphonic pressure from 0.0 to 1.0 before it was squeezed into
+// 7 bits, and is sent at full resolution instead of the message's own value.
+pub fn encode_ump(msg: &Message, value: Option<f32>, group: u8, out: &mut Vec<u32>)
+    -> Result<(), Box<dyn Error>> {
+    let mut bytes = Vec::with_capacity(3);
+    encode_message(msg, &mut bytes)?;
+
+    if bytes.is_empty() {
+        return Err(format!("{:?} encoded to nothing", msg).into());
+    }
+
+    let group = (group & 0x0F) as u32;
+
+    if bytes[0] >= 0xF8 {
+        out.push(UMP_SYSTEM << 28 | group << 24 | (bytes[0] as u32) << 16);
+        return Ok(());
+    }
+
+    let mut status = bytes[0] as u32;
+    let data1 = bytes.get(1).cloned().unwrap_or(0) as u32;
+    let data2 = bytes.get(2).cloned().unwrap_or(0) as u32;
+
+    // a MIDI 1.0 note on with a velocity of zero is a note off. in MIDI 2.0 it'd be an actual
+    // note on, so it's translated.
+    if status & 0xF0 == 0x90 && data2 == 0 && value.is_none() {
+        status = 0x80 | (status & 0x0F);
+    }
+
+    let (index, data) = match status & 0xF0 {
+        0x80 => (data1 << 8, scale_up(data2, 7, 16) << 16),
+
+        // a velocity of zero isn't a note off any more in MIDI 2.0, but it still reads
+        // like one to plenty of receivers, so the quietest note on is 1.
+        0x90 => (data1 << 8, match value {
+            Some(value) => unit_to_u16(value).max(1) << 16,
+            None => scale_up(data2, 7, 16) << 16
+        }),
+
+        0xA0 => (data1 << 8, match value {
+            Some(value) => unit_to_u32(value),
+            None => scale_up(data2, 7, 32)
+        }),
+
+        0xB0 => (data1 << 8, scale_up(data2, 7, 32)),
+        0xC0 => (PROGRAM_CHANGE_NO_BANK, data1 << 24),
+        0xD0 => (0, scale_up(data1, 7, 32)),
+        0xE0 => (0, scale_up(data2 << 7 | data1, 14, 32)),
+
+        // sysex would need the 64-bit data messages, which nothing here sends yet.
+        _ => return Err("SysEx isn't supported by the UMP sink".into())
+    };
+
+    out.push(UMP_MIDI2_CHANNEL_VOICE << 28 | group << 24 | status << 16 | index);
+    out.push(data);
+    Ok(())
+}
+
+// writes UMP words to a file, which is meant to be one of ALSA's UMP devices like
+// /dev/snd/umpC1D0 (but a regular file will do as well). the words are written in the
+// machine's byte order, which is what ALSA expects.
+pub struct UmpSink {
+    file: File,
+    group: u8,
+    words: Vec<u32>
+}
+
+impl UmpSink {
+    pub fn new(file: File, group: u8) -> Self {
+        UmpSink {
+            file: file,
+            group: group,
+            words: Vec::new()
+        }
+    }
+}
+
+impl MidiSink for UmpSink {
+    fn send(&mut self, msg: &Message, _: Instant) -> Result<(), Box<dyn Error>> {
+        encode_ump(msg, None, self.group, &mut self.words)
+    }
+
+    fn send_high_res(&mut self, msg: &Message, value: f32, _: Instant)
+        -> Result<(), Box<dyn Error>> {
+        encode_ump(msg, Some(value), self.group, &mut self.words)
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.words.is_empty() {
+            return Ok(());
+        }
+
+        let mut bytes = Vec::with_capacity(self.words.len() * 4);
+
+        for word in self.words.iter() {
+            bytes.extend_from_slice(&word.to_ne_bytes());
+        }
+
+        self.words.clear();
+
+        self.file.write_all(&bytes)?;
+        Ok(())
+    }
+}
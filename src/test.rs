@@ -0,0 +1,1288 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::net::{
+    SocketAddr,
+    UdpSocket
+};
+use std::os::unix::io::RawFd;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+use nix::unistd;
+
+use midi::*;
+use osc;
+
+use base::font::render_text;
+use base::crosstalk::are_neighbours;
+use base::gesture::Gesture;
+use devices::mk2::Mikro;
+use reactor::Reactor;
+
+use base::{
+    ButtonGestures,
+    CrosstalkFilter,
+    Maschine,
+    MaschineHandler,
+    MaschineButton
+};
+
+use sink::{
+    MidiSink,
+    RawMidiSink,
+    RtpMidiSink,
+    UmpSink,
+    RecordingSink,
+    encode_message,
+    encode_ump
+};
+
+#[cfg(feature = "jack")]
+use sink::JackSink;
+
+use config::Config;
+
+use encoder::{
+    EncoderCc,
+    EncoderMode
+};
+
+use bank::{
+    banks_from_config,
+    default_banks,
+    global_channel_from_config
+};
+
+use scale::{
+    SCALES,
+    parse_root,
+    scale_by_name
+};
+
+use layout::{
+    Layout,
+    PadColor,
+    layouts_from_config
+};
+
+use {
+    Controller,
+    MHandler,
+    PressureShape,
+    controller_readable,
+    tick_controllers
+};
+
+// stands in for a device, for driving a handler without any hardware.
+struct FakeMaschine {
+    midi_note_base: u8,
+    pad_lights: [(u32, f32); 16],
+    button_lights: Vec<(MaschineButton, u32, f32)>,
+    screen: Vec<String>
+}
+
+impl FakeMaschine {
+    fn new(midi_note_base: u8) -> Self {
+        FakeMaschine {
+            midi_note_base: midi_note_base,
+            pad_lights: [(0, 0.0); 16],
+            button_lights: Vec::new(),
+            screen: vec![String::new(); 8]
+        }
+    }
+}
+
+impl Maschine for FakeMaschine {
+    fn get_fd(&self) -> RawFd { -1 }
+
+    fn get_pad_pressure(&self, _: usize) -> Result<f32, ()> { Ok(0.0) }
+
+    fn get_midi_note_base(&self) -> u8 { self.midi_note_base }
+    fn set_midi_note_base(&mut self, base: u8) { self.midi_note_base = base }
+
+    fn set_crosstalk_rejection(&mut self, _: f32, _: Duration) {}
+    fn set_gesture_timings(&mut self, _: Duration, _: Duration) {}
+
+    fn set_pad_light(&mut self, pad_idx: usize, color: u32, brightness: f32) {
+        self.pad_lights[pad_idx] = (color, brightness);
+    }
+    fn set_button_light(&mut self, btn: MaschineButton, color: u32, brightness: f32) {
+        self.button_lights.push((btn, color, brightness));
+    }
+
+    fn readable(&mut self, _: &mut dyn MaschineHandler) -> io::Result<()> { Ok(()) }
+    fn tick(&mut self, _: &mut dyn MaschineHandler) {}
+
+    fn set_screen_text(&mut self, line: usize, text: &str) {
+        self.screen[line] = text.to_string();
+    }
+
+    fn clear_screen(&mut self) {}
+    fn write_screen(&mut self) -> io::Result<()> { Ok(()) }
+    fn write_lights(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+fn test_handler<'a>(osc_socket: &'a UdpSocket, midi: Box<dyn MidiSink>) -> MHandler<'a> {
+    MHandler {
+        midi: midi,
+        feedback_port: None,
+
+        pressure_shape: PressureShape::Linear,
+        send_aftertouch: true,
+        send_raw_pads: false,
+        send_timestamps: false,
+
+        layouts: Layout::builtin(),
+        layout_idx: 0,
+
+        channel: Ch1,
+
+        banks: default_banks(),
+        bank_idx: 0,
+
+        scale_idx: None,
+        root: 0,
+
+        held_buttons: Vec::new(),
+        held_notes: [None; 16],
+
+        osc_prefix: "/maschine".to_string(),
+        epoch: Instant::now(),
+
+        osc_socket: osc_socket,
+        osc_outgoing_addr: osc_socket.local_addr().unwrap()
+    }
+}
+
+fn encoded(msg: Message) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    encode_message(&msg, &mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn test_encode_message() {
+    assert_eq!(encoded(Message::NoteOn(Ch1, 60, 100)), vec![0x90, 60, 100]);
+    assert_eq!(encoded(Message::NoteOff(Ch10, 36, 0)), vec![0x89, 36, 0]);
+    assert_eq!(encoded(Message::PolyphonicPressure(Ch16, 1, 2)), vec![0xAF, 1, 2]);
+    assert_eq!(encoded(Message::ControlChange(Ch2, 7, 127)), vec![0xB1, 7, 127]);
+    assert_eq!(encoded(Message::AllNotesOff(Ch1)), vec![0xB0, 0x7B, 0]);
+    assert_eq!(encoded(Message::LocalControlOn(Ch1)), vec![0xB0, 0x7A, 0x7F]);
+    assert_eq!(encoded(Message::ProgramChange(Ch3, 5)), vec![0xC2, 5]);
+    assert_eq!(encoded(Message::ChannelPressure(Ch1, 64)), vec![0xD0, 64]);
+    assert_eq!(encoded(Message::PitchBend(Ch1, 0x2000)), vec![0xE0, 0x00, 0x40]);
+    assert_eq!(encoded(Message::PitchBend(Ch1, 0x3FFF)), vec![0xE0, 0x7F, 0x7F]);
+    assert_eq!(encoded(Message::TimingClock), vec![0xF8]);
+    assert_eq!(encoded(Message::SystemReset), vec![0xFF]);
+
+    assert_eq!(encoded(Message::SysEx(Manufacturer::OneByte(0x7E), vec![0x7F, 0x06, 0x01])),
+               vec![0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]);
+
+    assert!(encode_message(&Message::RPN7(Ch1, 0, 2), &mut Vec::new()).is_err());
+}
+
+#[test]
+fn test_handler_flushes_once_per_report() {
+    let osc_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let recording = RecordingSink::new();
+
+    let mut handler = test_handler(&osc_socket, Box::new(recording.clone()));
+    let mut maschine = FakeMaschine::new(36);
+    let now = Instant::now();
+
+    handler.pad_pressed(&mut maschine, 0, 1.0, now);
+    handler.pad_pressed(&mut maschine, 15, 1.0, now);
+    handler.pad_released(&mut maschine, 0, now);
+
+    let expected = vec![
+        Message::NoteOn(Ch1, 48, 127),
+        Message::NoteOn(Ch1, 39, 127),
+        Message::NoteOff(Ch1, 48, 0)
+    ];
+
+    assert_eq!(recording.pending(), expected);
+    assert!(recording.flushed().is_empty());
+
+    handler.report_processed(&mut maschine, now);
+
+    assert!(recording.pending().is_empty());
+    assert_eq!(recording.flushed(), expected);
+}
+
+// refuses everything, like a sink whose synth has gone away.
+struct FailingSink;
+
+impl MidiSink for FailingSink {
+    fn send(&mut self, _: &Message, _: Instant) -> Result<(), Box<dyn Error>> {
+        Err("synth went away".into())
+    }
+}
+
+#[test]
+fn test_handler_survives_send_errors() {
+    let osc_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let mut handler = test_handler(&osc_socket, Box::new(FailingSink));
+    let mut maschine = FakeMaschine::new(36);
+    let now = Instant::now();
+
+    handler.pad_pressed(&mut maschine, 0, 1.0, now);
+    assert_eq!(handler.held_notes[0], None);
+
+    handler.pad_aftertouch(&mut maschine, 0, 0.5, now);
+    handler.pad_released(&mut maschine, 0, now);
+    handler.encoder_step(&mut maschine, 0, 1, now);
+}
+
+#[test]
+fn test_reactor_removes_sources() {
+    let mut reactor = Reactor::new();
+
+    let timer = reactor.add_timer(Duration::from_millis(1), |ticks: &mut usize| {
+        *ticks += 1;
+        Ok(())
+    }).unwrap();
+
+    let mut ticks = 0;
+    reactor.turn(&mut ticks).unwrap();
+    assert_eq!(ticks, 1);
+
+    // with its only source gone, the reactor has nothing left to run.
+    reactor.remove(timer);
+    reactor.run(&mut ticks).unwrap();
+    assert_eq!(ticks, 1);
+}
+
+#[test]
+fn test_reactor_drops_hung_up_fds() {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    // the other end going away leaves the read end hung up for good, which would have the
+    // loop spinning if the source stayed around.
+    unsafe {
+        libc::close(fds[1]);
+    }
+
+    let mut reactor = Reactor::new();
+    reactor.add_fd(fds[0], |calls: &mut usize| {
+        *calls += 1;
+        Ok(())
+    });
+
+    // the callback gets to see the hangup once, then the source is gone.
+    let mut calls = 0;
+    reactor.run(&mut calls).unwrap();
+    assert_eq!(calls, 1);
+
+    unsafe {
+        libc::close(fds[0]);
+    }
+}
+
+#[test]
+fn test_reactor_drops_failed_sources() {
+    let mut reactor = Reactor::new();
+
+    reactor.add_timer(Duration::from_millis(1), |ticks: &mut usize| {
+        *ticks += 1;
+
+        match *ticks {
+            3 => Err(io::Error::from_raw_os_error(libc::EIO)),
+            _ => Ok(())
+        }
+    }).unwrap();
+
+    let mut ticks = 0;
+    reactor.run(&mut ticks).unwrap();
+    assert_eq!(ticks, 3);
+}
+
+#[test]
+fn test_unplugged_mikro_reports_an_error() {
+    let osc_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut handler = test_handler(&osc_socket, Box::new(RecordingSink::new()));
+
+    // the write end closing is what an unplugged hidraw device looks like to the reader.
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    let mut mikro = Mikro::new(fds[0]);
+
+    unsafe {
+        libc::close(fds[1]);
+    }
+
+    assert!(mikro.readable(&mut handler).is_err());
+
+    unsafe {
+        libc::close(fds[0]);
+    }
+
+    assert!(mikro.write_lights().is_err());
+}
+
+// a socketpair rather than a pipe, so that lights and the screen can be written to the device
+// end as well as reports read from it.
+fn device_socketpair() -> [RawFd; 2] {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe {
+        libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr())
+    }, 0);
+
+    fds
+}
+
+#[test]
+fn test_unplugging_one_controller_leaves_the_others() {
+    let osc_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let first = device_socketpair();
+    let second = device_socketpair();
+
+    let mut controllers = vec![first[0], second[0]].into_iter().enumerate()
+        .map(|(idx, fd)| {
+            let mut handler = test_handler(&osc_socket, Box::new(RecordingSink::new()));
+            handler.osc_prefix = format!("/maschine/{}", idx + 1);
+
+            Controller {
+                dev: Box::new(Mikro::new(fd)),
+                handler: handler
+            }
+        })
+        .collect::<Vec<_>>();
+
+    tick_controllers(&mut controllers);
+    assert_eq!(controllers.len(), 2);
+
+    unsafe {
+        libc::close(first[1]);
+    }
+
+    assert!(controller_readable(&mut controllers, first[0]).is_err());
+    assert_eq!(controllers.len(), 1);
+    assert_eq!(controllers[0].handler.osc_prefix, "/maschine/2");
+
+    // its fd going off again finds nothing to read it.
+    assert!(controller_readable(&mut controllers, first[0]).is_err());
+
+    // the one that's still plugged in keeps working.
+    let enable = osc::Message {
+        path: "/maschine/2/raw_pads",
+        arguments: vec![osc::Argument::i(1)]
+    };
+
+    {
+        let ctl = &mut controllers[0];
+        ctl.handler.handle_osc_messge(&mut *ctl.dev, "/raw_pads", &enable);
+    }
+
+    let mut report = vec![0x20u8];
+    report.extend_from_slice(&[0u8; 16 * 2]);
+
+    unistd::write(second[1], &report).unwrap();
+    controller_readable(&mut controllers, second[0]).unwrap();
+
+    let (path, _) = recv_osc_ints(&osc_socket);
+    assert_eq!(path, "/maschine/2/pads/raw");
+
+    tick_controllers(&mut controllers);
+    assert_eq!(controllers.len(), 1);
+
+    // and is dropped in turn once it can't be written to.
+    unsafe {
+        libc::close(second[1]);
+    }
+
+    tick_controllers(&mut controllers);
+    assert!(controllers.is_empty());
+
+    unsafe {
+        libc::close(first[0]);
+        libc::close(second[0]);
+    }
+}
+
+// the path and arguments of the next OSC message on `socket`, for messages with only int
+// arguments.
+fn recv_osc_ints(socket: &UdpSocket) -> (String, Vec<i32>) {
+    let mut buf = [0u8; 256];
+    let nbytes = socket.recv(&mut buf).unwrap();
+
+    let msg = match osc::Message::deserialize(&buf[.. nbytes]) {
+        Ok(msg) => msg,
+        Err(_) => panic!("couldn't decode OSC message")
+    };
+
+    let ints = msg.arguments.iter()
+        .map(|arg| match *arg {
+            osc::Argument::i(val) => val,
+            _ => panic!("non-int argument in {}", msg.path)
+        })
+        .collect();
+
+    (msg.path.to_string(), ints)
+}
+
+#[test]
+fn test_osc_timestamps() {
+    let osc_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let mut handler = test_handler(&osc_socket, Box::new(RecordingSink::new()));
+    let mut maschine = FakeMaschine::new(48);
+    let time = handler.epoch + Duration::new(3, 250_000);
+
+    // the messages look the same as they always have until timestamps are asked for.
+    handler.button_down(&mut maschine, MaschineButton::Play, time);
+    assert_eq!(recv_osc_ints(&osc_socket), ("/maschine/button/play".to_string(), vec![1]));
+
+    let enable = osc::Message {
+        path: "/maschine/timestamps",
+        arguments: vec![osc::Argument::i(1)]
+    };
+
+    handler.handle_osc_messge(&mut maschine, "/timestamps", &enable);
+
+    handler.button_up(&mut maschine, MaschineButton::Play, time);
+    assert_eq!(recv_osc_ints(&osc_socket),
+               ("/maschine/button/play".to_string(), vec![0, 3, 250]));
+
+    handler.encoder_step(&mut maschine, 0, -1, time);
+    assert_eq!(recv_osc_ints(&osc_socket), ("/maschine/encoder".to_string(), vec![-1, 3, 250]));
+}
+
+#[test]
+fn test_raw_pads_stream() {
+    let osc_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut handler = test_handler(&osc_socket, Box::new(RecordingSink::new()));
+
+    // a pipe stands in for the hidraw device, so that the report goes through the mikro's
+    // own parsing.
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    let mut mikro = Mikro::new(fds[0]);
+
+    // the device puts the pad number in the top 4 bits of each pad's word, which mustn't
+    // end up in the stream.
+    let mut report = vec![0x20u8];
+    for pad_idx in 0 .. 16u16 {
+        let word = (pad_idx << 12) | (pad_idx * 273);
+        report.extend_from_slice(&[(word & 0xFF) as u8, (word >> 8) as u8]);
+    }
+
+    let mut buf = [0u8; 256];
+
+    // the stream is off until it's asked for.
+    unistd::write(fds[1], &report).unwrap();
+    mikro.readable(&mut handler).unwrap();
+
+    osc_socket.set_nonblocking(true).unwrap();
+    assert!(osc_socket.recv(&mut buf).is_err());
+    osc_socket.set_nonblocking(false).unwrap();
+
+    let enable = osc::Message {
+        path: "/maschine/raw_pads",
+        arguments: vec![osc::Argument::i(1)]
+    };
+
+    handler.handle_osc_messge(&mut mikro, "/raw_pads", &enable);
+
+    let before = handler.epoch.elapsed();
+    unistd::write(fds[1], &report).unwrap();
+    mikro.readable(&mut handler).unwrap();
+    let after = handler.epoch.elapsed();
+
+    // a truncated report is skipped rather than read past its end.
+    unistd::write(fds[1], &report[.. 9]).unwrap();
+    mikro.readable(&mut handler).unwrap();
+
+    unsafe {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+    }
+
+    let (path, ints) = recv_osc_ints(&osc_socket);
+
+    assert_eq!(path, "/maschine/pads/raw");
+    assert_eq!(ints.len(), 18);
+    assert_eq!(&ints[.. 16], &(0 .. 16).map(|pad_idx| pad_idx * 273).collect::<Vec<_>>()[..]);
+
+    // the seconds and microseconds since the handler's epoch at which the report was read.
+    let micros = ints[16] as u64 * 1_000_000 + ints[17] as u64;
+    assert!(ints[17] < 1_000_000);
+    assert!(micros >= before.as_secs() * 1_000_000 + before.subsec_micros() as u64);
+    assert!(micros <= after.as_secs() * 1_000_000 + after.subsec_micros() as u64);
+
+    osc_socket.set_nonblocking(true).unwrap();
+    assert!(osc_socket.recv(&mut buf).is_err());
+}
+
+#[test]
+fn test_crosstalk_neighbours() {
+    // pad 5 is in the second row, second from the left.
+    assert!(are_neighbours(5, 1));
+    assert!(are_neighbours(5, 4));
+    assert!(are_neighbours(5, 6));
+    assert!(are_neighbours(5, 9));
+
+    // corners touch as well.
+    assert!(are_neighbours(5, 0));
+    assert!(are_neighbours(5, 10));
+
+    assert!(!are_neighbours(5, 5));
+    assert!(!are_neighbours(5, 7));
+    assert!(!are_neighbours(5, 13));
+
+    // the end of one row and the start of the next are on opposite sides of the grid.
+    assert!(!are_neighbours(3, 4));
+}
+
+#[test]
+fn test_crosstalk_rejection() {
+    let mut filter = CrosstalkFilter::default();
+    let start = Instant::now();
+    let ms = |ms| start + Duration::from_millis(ms);
+
+    // a hard hit on pad 5, which builds up over a couple of reports.
+    filter.onset(5, 0.4, start);
+    assert!(!filter.is_crosstalk(5, start));
+    filter.aftertouch(5, 0.9, ms(2));
+
+    // a weak hit next to it, inside the window, is rejected, and stays that way until it's
+    // released.
+    filter.onset(6, 0.15, ms(5));
+    assert!(filter.is_crosstalk(6, ms(5)));
+    assert!(filter.is_suppressed(6));
+
+    filter.released(6);
+    assert!(!filter.is_suppressed(6));
+
+    // one that's strong enough of its own isn't.
+    filter.onset(6, 0.5, ms(5));
+    assert!(!filter.is_crosstalk(6, ms(5)));
+
+    // nor is a weak hit on a pad which isn't next to it.
+    filter.onset(15, 0.05, ms(5));
+    assert!(!filter.is_crosstalk(15, ms(5)));
+
+    // nor one after the window has passed.
+    filter.onset(1, 0.05, ms(20));
+    assert!(!filter.is_crosstalk(1, ms(20)));
+
+    // a ratio of 0 turns the filter off.
+    filter.configure(0.0, Duration::from_millis(10));
+    filter.onset(9, 0.05, ms(5));
+    assert!(!filter.is_crosstalk(9, ms(5)));
+}
+
+#[test]
+fn test_button_gestures() {
+    let mut gestures = ButtonGestures::default();
+    let start = Instant::now();
+    let ms = |ms| start + Duration::from_millis(ms);
+
+    // a long press fires once the button's been held for 500ms, and only once.
+    assert!(gestures.button_down(MaschineButton::Play, start).is_empty());
+    assert!(gestures.tick(ms(499)).is_empty());
+    assert_eq!(gestures.tick(ms(500)), vec![Gesture::LongPress(MaschineButton::Play)]);
+    assert!(gestures.tick(ms(900)).is_empty());
+    gestures.button_up(MaschineButton::Play);
+
+    // a double press is two presses within 300ms. a third press right after starts over.
+    assert!(gestures.button_down(MaschineButton::Rec, ms(1000)).is_empty());
+    gestures.button_up(MaschineButton::Rec);
+    assert_eq!(gestures.button_down(MaschineButton::Rec, ms(1300)),
+               vec![Gesture::DoublePress(MaschineButton::Rec)]);
+    gestures.button_up(MaschineButton::Rec);
+    assert!(gestures.button_down(MaschineButton::Rec, ms(1400)).is_empty());
+    gestures.button_up(MaschineButton::Rec);
+
+    assert!(gestures.button_down(MaschineButton::Erase, ms(2000)).is_empty());
+    gestures.button_up(MaschineButton::Erase);
+    assert!(gestures.button_down(MaschineButton::Erase, ms(2301)).is_empty());
+    gestures.button_up(MaschineButton::Erase);
+
+    // combos, with shift held. shift doesn't long press after being used in one, but the
+    // other button still does.
+    assert!(gestures.button_down(MaschineButton::Shift, ms(3000)).is_empty());
+    assert_eq!(gestures.button_down(MaschineButton::PadMode, ms(3100)),
+               vec![Gesture::ButtonCombo(MaschineButton::Shift, MaschineButton::PadMode)]);
+    assert_eq!(gestures.pad_pressed(7, 0.5),
+               vec![Gesture::PadCombo(MaschineButton::Shift, 7, 0.5)]);
+    assert_eq!(gestures.tick(ms(3600)), vec![Gesture::LongPress(MaschineButton::PadMode)]);
+
+    gestures.button_up(MaschineButton::PadMode);
+    gestures.button_up(MaschineButton::Shift);
+    assert!(gestures.pad_pressed(7, 0.5).is_empty());
+}
+
+#[test]
+fn test_modifier_pads_dont_play() {
+    let osc_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let recording = RecordingSink::new();
+
+    let mut handler = test_handler(&osc_socket, Box::new(recording.clone()));
+    let mut maschine = FakeMaschine::new(48);
+    let now = Instant::now();
+
+    handler.button_down(&mut maschine, MaschineButton::Shift, now);
+    handler.pad_pressed(&mut maschine, 12, 1.0, now);
+    handler.pad_combo(&mut maschine, MaschineButton::Shift, 12, 1.0, now);
+    handler.pad_released(&mut maschine, 12, now);
+    handler.button_up(&mut maschine, MaschineButton::Shift, now);
+
+    assert!(recording.pending().is_empty());
+
+    handler.pad_pressed(&mut maschine, 12, 1.0, now);
+    assert_eq!(recording.pending(), vec![Message::NoteOn(Ch1, 48, 127)]);
+}
+
+#[test]
+fn test_raw_midi_sink() {
+    let path = env::temp_dir().join(format!("maschine-rs-raw-midi-{}", std::process::id()));
+    File::create(&path).unwrap();
+
+    let mut sink = RawMidiSink::new(fs::OpenOptions::new().append(true).open(&path).unwrap());
+    let now = Instant::now();
+
+    sink.send(&Message::NoteOn(Ch1, 60, 100), now).unwrap();
+    sink.send(&Message::NoteOff(Ch1, 60, 0), now).unwrap();
+
+    let mut written = Vec::new();
+    File::open(&path).unwrap().read_to_end(&mut written).unwrap();
+    assert!(written.is_empty());
+
+    sink.flush().unwrap();
+
+    File::open(&path).unwrap().read_to_end(&mut written).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(written, vec![0x90, 60, 100, 0x80, 60, 0]);
+}
+
+fn ump(msg: Message, value: Option<f32>) -> Vec<u32> {
+    let mut words = Vec::new();
+    encode_ump(&msg, value, 0, &mut words).unwrap();
+    words
+}
+
+#[test]
+fn test_encode_ump() {
+    // 7-bit values are widened so that the center and the maximum stay where they are.
+    assert_eq!(ump(Message::NoteOn(Ch1, 60, 127), None), vec![0x40903C00, 0xFFFF0000]);
+    assert_eq!(ump(Message::NoteOn(Ch1, 60, 64), None), vec![0x40903C00, 0x80000000]);
+    assert_eq!(ump(Message::ControlChange(Ch2, 7, 127), None), vec![0x40B10700, 0xFFFFFFFF]);
+    assert_eq!(ump(Message::PitchBend(Ch1, 0x2000), None), vec![0x40E00000, 0x80000000]);
+    assert_eq!(ump(Message::ProgramChange(Ch3, 5), None), vec![0x40C20000, 0x05000000]);
+    assert_eq!(ump(Message::TimingClock, None), vec![0x10F80000]);
+
+    // a MIDI 1.0 note on without velocity is a note off.
+    assert_eq!(ump(Message::NoteOn(Ch1, 60, 0), None), vec![0x40803C00, 0x00000000]);
+
+    // with the full resolution value, it's used as it is.
+    assert_eq!(ump(Message::NoteOn(Ch1, 60, 63), Some(0.5)), vec![0x40903C00, 0x80000000]);
+    assert_eq!(ump(Message::NoteOn(Ch1, 60, 0), Some(0.0)), vec![0x40903C00, 0x00010000]);
+    assert_eq!(ump(Message::PolyphonicPressure(Ch16, 1, 31), Some(0.25)),
+               vec![0x40AF0100, 0x40000000]);
+
+    let mut words = Vec::new();
+    encode_ump(&Message::NoteOff(Ch1, 60, 0), None, 3, &mut words).unwrap();
+    assert_eq!(words, vec![0x43803C00, 0x00000000]);
+}
+
+#[test]
+fn test_ump_sink_keeps_pad_resolution() {
+    let path = env::temp_dir().join(format!("maschine-rs-ump-{}", std::process::id()));
+    File::create(&path).unwrap();
+
+    let sink = UmpSink::new(fs::OpenOptions::new().append(true).open(&path).unwrap(), 0);
+    let osc_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let mut handler = test_handler(&osc_socket, Box::new(sink));
+    let mut maschine = FakeMaschine::new(36);
+    let now = Instant::now();
+
+    // a 12-bit pad reading of 2049, which 7-bit velocity would round down to 64.
+    handler.pad_pressed(&mut maschine, 0, 2049.0 / 4095.0, now);
+    handler.pad_aftertouch(&mut maschine, 0, 1.0, now);
+    handler.report_processed(&mut maschine, now);
+
+    let mut written = Vec::new();
+    File::open(&path).unwrap().read_to_end(&mut written).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let words: Vec<u32> = written.chunks(4)
+        .map(|word| u32::from_ne_bytes([word[0], word[1], word[2], word[3]]))
+        .collect();
+
+    let note = 48;
+
+    assert_eq!(words, vec![
+        0x40900000 | note << 8, ((2049.0 / 4095.0 * 65535.0f64).round() as u32) << 16,
+        0x40A00000 | note << 8, 0xFFFFFFFF
+    ]);
+}
+
+#[test]
+fn test_config_parse() {
+    let config = Config::parse("
+        # a comment
+        layout = drums   # and another
+
+        [layout my drums]
+        notes = 1 2 3
+        relative = yes
+    ").unwrap();
+
+    assert_eq!(config.global().get("layout").unwrap().value, "drums");
+
+    let sections: Vec<_> = config.sections("layout").collect();
+    assert_eq!(sections.len(), 1);
+    assert_eq!(sections[0].name, Some("my drums".to_string()));
+    assert_eq!(sections[0].get("notes").unwrap().value, "1 2 3");
+    assert_eq!(sections[0].get("relative").unwrap().line, 7);
+
+    assert_eq!(Config::parse("[layout").err().unwrap(), "line 1: unterminated section header");
+    assert_eq!(Config::parse("\nnotes").err().unwrap(), "line 2: expected \"key = value\"");
+}
+
+#[test]
+fn test_layouts_from_config() {
+    let config = Config::parse("
+        layout = mine
+
+        [layout mine]
+        notes = 60 61 62 63  64 65 66 67  68 69 70 71  72 73 74 127
+        colors = ff0000 off default default  default default default default  default default default default  default default default default
+    ").unwrap();
+
+    let (layouts, active) = layouts_from_config(&config).unwrap();
+    let names: Vec<_> = layouts.iter().map(|l| &*l.name).collect();
+
+    assert_eq!(names, vec!["chromatic", "gm_drums", "fourths", "mine"]);
+    assert_eq!(active, 3);
+
+    // custom notes are absolute unless they're marked relative.
+    assert_eq!(layouts[3].note(36, 0), Some(60));
+    assert_eq!(layouts[3].note(36, 15), Some(127));
+    assert_eq!(layouts[3].pad_color(36, 0), PadColor::Rgb(0xFF0000));
+    assert_eq!(layouts[3].pad_color(36, 1), PadColor::Off);
+
+    // the bottom left pad plays the note base, up to where the note range runs out.
+    assert_eq!(layouts[0].note(36, 12), Some(36));
+    assert_eq!(layouts[0].note(36, 3), Some(51));
+    assert_eq!(layouts[0].note(120, 3), None);
+    assert_eq!(layouts[2].note(36, 4), Some(46));
+    assert_eq!(layouts[1].note(0, 12), Some(36));
+
+    let bad = Config::parse("[layout short]\nnotes = 1 2 3").unwrap();
+    assert_eq!(layouts_from_config(&bad).err().unwrap(), "line 2: a layout needs 16 notes, not 3");
+
+    let missing = Config::parse("layout = nope").unwrap();
+    assert_eq!(layouts_from_config(&missing).err().unwrap(), "line 1: no layout called nope");
+}
+
+#[test]
+fn test_chromatic_black_keys_follow_the_note_base() {
+    let chromatic = Layout::chromatic();
+
+    // from C, the second pad along the bottom row is C#.
+    assert_eq!(chromatic.pad_color(48, 12), PadColor::Default);
+    assert_eq!(chromatic.pad_color(48, 13), PadColor::Off);
+    assert_eq!(chromatic.pad_color(48, 8), PadColor::Default);
+
+    // from D, it's D# and the first pad of the next row up is F#.
+    assert_eq!(chromatic.pad_color(50, 12), PadColor::Default);
+    assert_eq!(chromatic.pad_color(50, 13), PadColor::Off);
+    assert_eq!(chromatic.pad_color(50, 14), PadColor::Default);
+    assert_eq!(chromatic.pad_color(50, 15), PadColor::Default);
+    assert_eq!(chromatic.pad_color(50, 8), PadColor::Off);
+
+    // from C#, the bottom left pad is a black key itself.
+    assert_eq!(chromatic.pad_color(49, 12), PadColor::Off);
+    assert_eq!(chromatic.pad_color(49, 13), PadColor::Default);
+
+    // and the pads are lit again when the note base changes.
+    let osc_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut handler = test_handler(&osc_socket, Box::new(RecordingSink::new()));
+    let mut maschine = FakeMaschine::new(48);
+
+    handler.select_bank(&mut maschine, 0);
+
+    let base = osc::Message {
+        path: "/maschine/midi_note_base",
+        arguments: vec![osc::Argument::i(49)]
+    };
+
+    handler.handle_osc_messge(&mut maschine, "/midi_note_base", &base);
+
+    assert_eq!(maschine.pad_lights[12].1, 0.0);
+    assert!(maschine.pad_lights[13].1 > 0.0);
+}
+
+#[test]
+fn test_layout_switch() {
+    let osc_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let recording = RecordingSink::new();
+
+    let mut handler = test_handler(&osc_socket, Box::new(recording.clone()));
+    let mut maschine = FakeMaschine::new(36);
+    let now = Instant::now();
+
+    handler.pad_pressed(&mut maschine, 12, 1.0, now);
+    handler.button_combo(&mut maschine, MaschineButton::Shift, MaschineButton::PadMode, now);
+
+    // the held pad still lets go of the note it started, and the rest show the drum kit.
+    handler.pad_released(&mut maschine, 12, now);
+    handler.pad_pressed(&mut maschine, 12, 1.0, now);
+
+    assert_eq!(recording.pending(), vec![
+        Message::NoteOn(Ch1, 36, 127),
+        Message::NoteOff(Ch1, 36, 0),
+        Message::NoteOn(Ch1, 36, 127)
+    ]);
+
+    assert_eq!(handler.layout().name, "gm_drums");
+    assert_eq!(maschine.pad_lights[0].0, 0xA000FF);
+    assert_eq!(maschine.pad_lights[15].0, 0xFFD000);
+}
+
+#[test]
+fn test_render_text() {
+    let mut columns = [0xFFu8; 16];
+    render_text("A!\u{e9}", &mut columns);
+
+    assert_eq!(&columns[.. 6], &[0x7E, 0x11, 0x11, 0x11, 0x7E, 0x00]);
+    assert_eq!(&columns[6 .. 12], &[0x00, 0x00, 0x5F, 0x00, 0x00, 0x00]);
+
+    // what doesn't fit is cut off, and anything unprintable is a question mark.
+    assert_eq!(&columns[12 ..], &[0x02, 0x01, 0x51, 0x09]);
+}
+
+#[test]
+fn test_scales() {
+    assert_eq!(parse_root("D"), Some(2));
+    assert_eq!(parse_root("f#"), Some(6));
+    assert_eq!(parse_root("Bb"), Some(10));
+    assert_eq!(parse_root("Cb"), Some(11));
+    assert_eq!(parse_root("H"), None);
+
+    // the bottom row starts on the root in the note base's octave, and carries on up.
+    let dorian = &SCALES[scale_by_name("dorian").unwrap()];
+    let bottom_row: Vec<_> = (12 .. 16).map(|pad| dorian.note(2, 48, pad).unwrap()).collect();
+    let second_row: Vec<_> = (8 .. 12).map(|pad| dorian.note(2, 48, pad).unwrap()).collect();
+
+    assert_eq!(bottom_row, vec![50, 52, 53, 55]);
+    assert_eq!(second_row, vec![57, 59, 60, 62]);
+
+    assert!(dorian.is_root(12));
+    assert!(dorian.is_root(11));
+    assert!(!dorian.is_root(8));
+}
+
+#[test]
+fn test_scale_selection() {
+    let osc_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let recording = RecordingSink::new();
+
+    let mut handler = test_handler(&osc_socket, Box::new(recording.clone()));
+    let mut maschine = FakeMaschine::new(48);
+    let now = Instant::now();
+
+    // f2 and the encoder pick the scale, f1 and the encoder the root.
+    handler.button_down(&mut maschine, MaschineButton::F2, now);
+    handler.encoder_step(&mut maschine, 0, 2, now);
+    handler.button_up(&mut maschine, MaschineButton::F2, now);
+
+    handler.button_down(&mut maschine, MaschineButton::F1, now);
+    handler.encoder_step(&mut maschine, 0, -3, now);
+    handler.button_up(&mut maschine, MaschineButton::F1, now);
+
+    assert_eq!(maschine.screen[0], "scale: minor");
+    assert_eq!(maschine.screen[1], "root: A");
+
+    handler.pad_pressed(&mut maschine, 13, 1.0, now);
+    assert_eq!(recording.pending(), vec![Message::NoteOn(Ch1, 59, 127)]);
+
+    // the roots stand out from the rest.
+    assert_eq!(maschine.pad_lights[12].0, 0x0040FF);
+    assert_eq!(maschine.pad_lights[11].0, 0x0040FF);
+    assert!(maschine.pad_lights[14].0 != 0x0040FF);
+
+    // and back around to the layout.
+    handler.button_down(&mut maschine, MaschineButton::F2, now);
+    handler.encoder_step(&mut maschine, 0, -2, now);
+
+    assert_eq!(maschine.screen[0], "layout: chromatic");
+    assert_eq!(maschine.screen[1], "");
+}
+
+#[test]
+fn test_banks_from_config() {
+    let config = Config::parse("
+        [bank b]
+        note_base = 36
+        channel = 10
+        color = 00ff00
+    ").unwrap();
+
+    let banks = banks_from_config(&config).unwrap();
+
+    assert_eq!(banks.len(), 8);
+    assert_eq!((banks[1].note_base, banks[1].channel, banks[1].color),
+               (36, Some(Ch10), 0x00FF00));
+    assert_eq!((banks[0].note_base, banks[0].channel), (48, None));
+
+    let bad = Config::parse("[bank I]").unwrap();
+    assert_eq!(banks_from_config(&bad).err().unwrap(), "line 1: banks are called A to H");
+
+    let bad = Config::parse("[bank A]\nchannel = 17").unwrap();
+    assert_eq!(banks_from_config(&bad).err().unwrap(), "line 2: bad MIDI channel 17");
+}
+
+#[test]
+fn test_bank_selection() {
+    let osc_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let recording = RecordingSink::new();
+
+    let mut handler = test_handler(&osc_socket, Box::new(recording.clone()));
+    let mut maschine = FakeMaschine::new(48);
+    let now = Instant::now();
+
+    handler.banks[2].note_base = 60;
+    handler.banks[2].channel = Some(Ch3);
+
+    handler.pad_pressed(&mut maschine, 12, 1.0, now);
+
+    // group and pad 3 (bottom row, third from the left) picks bank C, without playing.
+    handler.button_down(&mut maschine, MaschineButton::Group, now);
+    handler.pad_pressed(&mut maschine, 14, 1.0, now);
+    handler.pad_released(&mut maschine, 14, now);
+    handler.button_up(&mut maschine, MaschineButton::Group, now);
+
+    handler.pad_released(&mut maschine, 12, now);
+    handler.pad_pressed(&mut maschine, 12, 1.0, now);
+
+    assert_eq!(recording.pending(), vec![
+        Message::NoteOn(Ch1, 48, 127),
+        Message::NoteOff(Ch1, 48, 0),
+        Message::NoteOn(Ch3, 60, 127)
+    ]);
+
+    assert_eq!(maschine.midi_note_base, 60);
+    assert_eq!(maschine.screen[2], "bank: C");
+    assert_eq!(maschine.button_lights.last().unwrap(),
+               &(MaschineButton::Group, handler.banks[2].color, 1.0));
+    assert_eq!(maschine.pad_lights[14].0, handler.banks[2].color);
+}
+
+#[test]
+fn test_midi_channels() {
+    let config = Config::parse("
+        channel = 2
+
+        [bank A]
+        pad_channels = - - - -  - - - -  - - - -  10 10 11 11
+        [bank B]
+        channel = 3
+    ").unwrap();
+
+    assert_eq!(global_channel_from_config(&config).unwrap(), Ch2);
+
+    let banks = banks_from_config(&config).unwrap();
+
+    // a pad's own channel wins over its bank's, which wins over the global one.
+    assert_eq!(banks[0].pad_channel(12, Ch2), Ch10);
+    assert_eq!(banks[0].pad_channel(14, Ch2), Ch11);
+    assert_eq!(banks[0].pad_channel(0, Ch2), Ch2);
+    assert_eq!(banks[1].pad_channel(12, Ch2), Ch3);
+
+    let osc_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let recording = RecordingSink::new();
+
+    let mut handler = test_handler(&osc_socket, Box::new(recording.clone()));
+    let mut maschine = FakeMaschine::new(48);
+    let now = Instant::now();
+
+    handler.channel = Ch2;
+    handler.banks = banks;
+
+    // feedback only lights the pads on the note's channel.
+    handler.midi_feedback(&mut maschine, &Message::NoteOn(Ch2, 48, 127));
+    assert_eq!(maschine.pad_lights[12].1, 0.0);
+
+    handler.midi_feedback(&mut maschine, &Message::NoteOn(Ch10, 48, 127));
+    assert_eq!(maschine.pad_lights[12].1, 1.0);
+
+    handler.pad_pressed(&mut maschine, 12, 1.0, now);
+    handler.pad_pressed(&mut maschine, 14, 1.0, now);
+    handler.pad_pressed(&mut maschine, 0, 1.0, now);
+
+    assert_eq!(recording.pending(), vec![
+        Message::NoteOn(Ch10, 48, 127),
+        Message::NoteOn(Ch11, 50, 127),
+        Message::NoteOn(Ch2, 60, 127)
+    ]);
+
+    let set_channel = |handler: &mut MHandler, maschine: &mut FakeMaschine, path, args| {
+        let msg = osc::Message {
+            path: path,
+            arguments: args
+        };
+
+        handler.handle_osc_messge(maschine, path, &msg);
+    };
+
+    // out of range channels are ignored, 0 clears the override.
+    set_channel(&mut handler, &mut maschine, "/midi_channel/pad", vec![osc::Argument::i(12),
+                                                                        osc::Argument::i(-1)]);
+    set_channel(&mut handler, &mut maschine, "/midi_channel/pad", vec![osc::Argument::i(14),
+                                                                        osc::Argument::i(17)]);
+    assert_eq!(handler.banks[0].pad_channel(12, Ch2), Ch10);
+    assert_eq!(handler.banks[0].pad_channel(14, Ch2), Ch11);
+
+    set_channel(&mut handler, &mut maschine, "/midi_channel/pad", vec![osc::Argument::i(12),
+                                                                        osc::Argument::i(0)]);
+    assert_eq!(handler.banks[0].pad_channel(12, Ch2), Ch2);
+
+    set_channel(&mut handler, &mut maschine, "/midi_channel/bank", vec![osc::Argument::i(5)]);
+    set_channel(&mut handler, &mut maschine, "/midi_channel/bank", vec![osc::Argument::i(-3)]);
+    assert_eq!(handler.banks[0].channel, Some(Ch5));
+
+    set_channel(&mut handler, &mut maschine, "/midi_channel/bank", vec![osc::Argument::i(0)]);
+    assert_eq!(handler.banks[0].channel, None);
+}
+
+#[test]
+fn test_encoder_cc() {
+    let steps = |mode| {
+        let mut encoder = EncoderCc::new(20, mode);
+        [1, -1, 3, -3, 100].iter().map(|&delta| encoder.step(delta)).collect::<Vec<_>>()
+    };
+
+    assert_eq!(steps(EncoderMode::TwosComplement),
+               vec![Some(1), Some(127), Some(3), Some(125), Some(63)]);
+    assert_eq!(steps(EncoderMode::SignMagnitude),
+               vec![Some(1), Some(65), Some(3), Some(67), Some(63)]);
+    assert_eq!(steps(EncoderMode::BinaryOffset),
+               vec![Some(65), Some(63), Some(67), Some(61), Some(127)]);
+
+    // absolute mode stays in its range, and has nothing to send at either end of it.
+    assert_eq!(steps(EncoderMode::Absolute { min: 10, max: 110 }),
+               vec![Some(11), Some(10), Some(13), Some(10), Some(110)]);
+
+    let mut encoder = EncoderCc::new(20, EncoderMode::Absolute { min: 0, max: 127 });
+    assert_eq!(encoder.step(-1), None);
+    assert_eq!(encoder.step(0), None);
+}
+
+#[test]
+fn test_encoder_cc_banks() {
+    let config = Config::parse("
+        [bank A]
+        encoder_cc = 20
+        encoder_min = 64
+        [bank B]
+        channel = 3
+        encoder_cc = 21
+        encoder_mode = twos_complement
+    ").unwrap();
+
+    let banks = banks_from_config(&config).unwrap();
+
+    let bad = Config::parse("[bank A]\nencoder_mode = sideways").unwrap();
+    assert_eq!(banks_from_config(&bad).err().unwrap(), "line 2: unknown encoder mode sideways");
+
+    let osc_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let recording = RecordingSink::new();
+
+    let mut handler = test_handler(&osc_socket, Box::new(recording.clone()));
+    let mut maschine = FakeMaschine::new(48);
+    let now = Instant::now();
+
+    handler.banks = banks;
+
+    handler.encoder_step(&mut maschine, 0, 2, now);
+    handler.select_bank(&mut maschine, 1);
+    handler.encoder_step(&mut maschine, 0, -1, now);
+
+    // bank A's value is kept while bank B is active.
+    handler.select_bank(&mut maschine, 0);
+    handler.encoder_step(&mut maschine, 0, -1, now);
+
+    // no CC without a mapping, or while the encoder is picking the root.
+    handler.select_bank(&mut maschine, 2);
+    handler.encoder_step(&mut maschine, 0, 1, now);
+    handler.select_bank(&mut maschine, 0);
+    handler.button_down(&mut maschine, MaschineButton::F1, now);
+    handler.encoder_step(&mut maschine, 0, 1, now);
+
+    assert_eq!(recording.pending(), vec![
+        Message::ControlChange(Ch1, 20, 66),
+        Message::ControlChange(Ch3, 21, 127),
+        Message::ControlChange(Ch1, 20, 65)
+    ]);
+}
+
+// the responder's side of an AppleMIDI session, just enough of it to accept one invitation,
+// go through a clock sync and catch the first RTP-MIDI packet. returns that packet, and the
+// session's closing BY.
+type RtpMidiTestPeer = thread::JoinHandle<(Vec<u8>, Vec<u8>)>;
+
+fn rtp_midi_test_peer() -> (SocketAddr, RtpMidiTestPeer) {
+    let (control, data) = loop {
+        let control = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let data_port = control.local_addr().unwrap().port().wrapping_add(1);
+
+        if let Ok(data) = UdpSocket::bind(("127.0.0.1", data_port)) {
+            break (control, data);
+        }
+    };
+
+    let addr = control.local_addr().unwrap();
+
+    let peer = thread::spawn(move || {
+        let mut buf = [0u8; 256];
+
+        control.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        data.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // the invitation, on the control port then the data port. the answer echoes the
+        // initiator's token.
+        for socket in [&control, &data].iter() {
+            let (len, from) = socket.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[.. 4], b"\xFF\xFFIN");
+            assert_eq!(&buf[4 .. 8], &[0, 0, 0, 2]);
+            assert_eq!(&buf[len - 12 .. len], b"maschine.rs\0");
+
+            let mut ok = buf[.. 16].to_vec();
+            ok[2 .. 4].copy_from_slice(b"OK");
+            ok[12 .. 16].copy_from_slice(&[0xAB, 0xCD, 0xEF, 0x01]);
+            ok.extend_from_slice(b"test peer\0");
+
+            socket.send_to(&ok, from).unwrap();
+        }
+
+        let mut rtp_packet = None;
+        let mut synced = false;
+
+        while rtp_packet.is_none() || !synced {
+            let (len, from) = data.recv_from(&mut buf).unwrap();
+
+            if buf[0] == 0x80 {
+                rtp_packet = Some(buf[.. len].to_vec());
+                continue;
+            }
+
+            assert_eq!(&buf[.. 4], b"\xFF\xFFCK");
+
+            match buf[8] {
+                0 => {
+                    let mut ck = buf[.. 36].to_vec();
+                    ck[4 .. 8].copy_from_slice(&[0xAB, 0xCD, 0xEF, 0x01]);
+                    ck[8] = 1;
+                    ck[20 .. 28].copy_from_slice(&[0, 0, 0, 0, 0, 0, 0x12, 0x34]);
+
+                    data.send_to(&ck, from).unwrap();
+                },
+
+                2 => {
+                    assert_eq!(&buf[20 .. 28], &[0, 0, 0, 0, 0, 0, 0x12, 0x34]);
+                    synced = true;
+                },
+
+                count => panic!("unexpected sync count {}", count)
+            }
+        }
+
+        let (len, _) = control.recv_from(&mut buf).unwrap();
+        (rtp_packet.unwrap(), buf[.. len].to_vec())
+    });
+
+    (addr, peer)
+}
+
+#[test]
+fn test_rtp_midi_sink() {
+    let (addr, peer) = rtp_midi_test_peer();
+
+    let mut sink = RtpMidiSink::connect(addr, "maschine.rs").unwrap();
+    let now = Instant::now();
+
+    sink.send(&Message::NoteOn(Ch1, 60, 100), now).unwrap();
+    sink.send(&Message::NoteOff(Ch1, 60, 0), now + Duration::from_millis(20)).unwrap();
+    sink.flush().unwrap();
+
+    // give the session thread the chance to finish syncing before the BY.
+    thread::sleep(Duration::from_millis(100));
+    drop(sink);
+
+    let (rtp_packet, bye) = peer.join().unwrap();
+
+    // version 2, MIDI payload type, first sequence number.
+    assert_eq!(&rtp_packet[.. 4], &[0x80, 0x61, 0, 0]);
+
+    // short command section header without a journal, then the two commands with 200
+    // (100µs ticks) of delta time in between.
+    assert_eq!(&rtp_packet[12 ..], &[0x08, 0x90, 60, 100, 0x81, 0x48, 0x80, 60, 0]);
+
+    assert_eq!(&bye[.. 4], b"\xFF\xFFBY");
+    assert_eq!(&bye[12 .. 16], &rtp_packet[8 .. 12]);
+}
+
+// needs a JACK server to be running, e.g. `jackd -d dummy`. run with `cargo test --features
+// jack -- --ignored`.
+#[cfg(feature = "jack")]
+#[test]
+#[ignore]
+fn test_jack_sink() {
+    use std::sync::mpsc;
+    use jack;
+
+    let mut sink = JackSink::new("maschine.rs test", "pads").unwrap();
+
+    let (client, _) = jack::Client::new("maschine.rs test receiver",
+                                        jack::ClientOptions::NO_START_SERVER).unwrap();
+    let port = client.register_port("in", jack::MidiIn::default()).unwrap();
+    let in_name = port.name().unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+
+    let client = client.activate_async((), jack::ClosureProcessHandler::new(
+        move |_: &jack::Client, ps: &jack::ProcessScope| {
+            for ev in port.iter(ps) {
+                sender.send(ev.bytes.to_vec()).unwrap();
+            }
+
+            jack::Control::Continue
+        })).unwrap();
+
+    let out_name = client.as_client()
+        .ports(Some("maschine.rs test.*:pads"), None, jack::PortFlags::IS_OUTPUT)
+        .pop().unwrap();
+
+    client.as_client().connect_ports_by_name(&out_name, &in_name).unwrap();
+
+    sink.send(&Message::NoteOn(Ch1, 60, 100), Instant::now()).unwrap();
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(1)).unwrap(), vec![0x90, 60, 100]);
+}